@@ -1,17 +1,74 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
-use alpm::{Alpm, Group, Package};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use alpm::{Alpm, Db, Group, Package, PackageReason};
 use alpm_utils::DbListExt;
 use anyhow::{Context, anyhow, bail};
-use derive_more::Deref;
+use derive_more::{Deref, DerefMut};
 
+use crate::srcinfo::{self, Srcinfo};
 use crate::temp_db::TempAlpm;
 
 pub fn pacmanconf() -> anyhow::Result<pacmanconf::Config> {
     pacmanconf::Config::new().context("failed to read pacman.conf")
 }
 
-#[derive(Deref)]
+/// Architectures of packages which could be installed on the system
+pub fn architectures() -> anyhow::Result<Vec<String>> {
+    let conf = pacmanconf()?;
+    let mut archs: Vec<String> = conf
+        .architecture
+        .into_iter()
+        .map(|arch| {
+            if arch == "auto" {
+                std::env::consts::ARCH.to_owned()
+            } else {
+                arch
+            }
+        })
+        .collect();
+    archs.push(String::from("any"));
+    Ok(archs)
+}
+
+/// Filters for the list of installed packages. Enabled filters are combined, so the package
+/// must satisfy all of them.
+#[derive(Default, Clone, Copy)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct PkgFilter {
+    pub orphaned: bool,
+    pub foreign: bool,
+    pub explicit: bool,
+    pub deps: bool,
+}
+
+/// Package which satisfies a dependency
+pub enum Dependency<'a> {
+    /// Package from sync or local database
+    Pkg(&'a Package),
+    /// Not installed AUR package known from its `.SRCINFO`
+    Aur(Srcinfo),
+}
+
+impl<'a> Dependency<'a> {
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Pkg(pkg) => pkg.name(),
+            Self::Aur(srcinfo) => &srcinfo.name,
+        }
+    }
+
+    pub const fn as_pkg(&self) -> Option<&'a Package> {
+        match self {
+            Self::Pkg(pkg) => Some(pkg),
+            Self::Aur(_) => None,
+        }
+    }
+}
+
+#[derive(Deref, DerefMut)]
 pub struct PacrsAlpm(Alpm);
 
 impl PacrsAlpm {
@@ -22,14 +79,47 @@ impl PacrsAlpm {
         Ok(Self(alpm))
     }
 
-    pub const fn with_alpm(alpm: Alpm) -> Self {
-        Self(alpm)
+    /// Connection to the system installed into `root` with databases in `dbpath`. No sync
+    /// databases are registered
+    pub fn with_paths(root: &Path, dbpath: &Path) -> anyhow::Result<Self> {
+        let alpm = Alpm::new(root.as_os_str().as_bytes(), dbpath.as_os_str().as_bytes())
+            .with_context(|| format!("failed to open databases in '{}'", dbpath.display()))?;
+        Ok(Self(alpm))
     }
 
     pub fn is_installed_pkg(&self, pkg_name: &str) -> bool {
         self.localdb().pkg(pkg_name).is_ok()
     }
 
+    pub fn filtered_pkgs(&self, filter: PkgFilter) -> Vec<&Package> {
+        self.localdb()
+            .pkgs()
+            .iter()
+            .filter(|pkg| !filter.orphaned || is_orphaned(pkg))
+            .filter(|pkg| !filter.foreign || self.is_foreign_pkg(pkg))
+            .filter(|pkg| !filter.explicit || pkg.reason() == PackageReason::Explicit)
+            .filter(|pkg| !filter.deps || pkg.reason() == PackageReason::Depend)
+            .collect()
+    }
+
+    pub fn orphaned_pkgs(&self) -> Vec<&Package> {
+        self.filtered_pkgs(PkgFilter {
+            orphaned: true,
+            ..PkgFilter::default()
+        })
+    }
+
+    /// Name of the sync database which contains the package
+    pub fn pkg_repo(&self, name: &str) -> Option<&str> {
+        let pkg = self.syncdbs().pkg(name).ok()?;
+        pkg.db().map(Db::name)
+    }
+
+    /// Package which not found in sync databases. In most cases it's AUR package
+    pub fn is_foreign_pkg(&self, pkg: &Package) -> bool {
+        self.syncdbs().pkg(pkg.name()).is_err()
+    }
+
     pub fn is_pkg_outdated(&self, alpm_tmp: &TempAlpm, package: &str) -> anyhow::Result<bool> {
         let pkg = self.syncdb_pkg(package)?;
         let pkg_tmp = alpm_tmp.syncdb_pkg(package)?;
@@ -56,36 +146,42 @@ impl PacrsAlpm {
                 // We assume that if you could not find dependencies, then the package from AUR and we ignore it
                 .unwrap_or_default()
                 .into_iter()
-                .map(|dep| dep.name());
+                .filter_map(|dep| dep.as_pkg())
+                .map(|pkg| pkg.name());
             for_check.extend(deps);
             already_checked.push(pkg);
         }
         outdated_pkgs
     }
 
-    pub fn dependencies<'a>(&'a self, package: &str) -> anyhow::Result<Vec<&'a Package>> {
-        // TODO: AUR support
-        let pkg = self.syncdbs().pkg(package);
-        if let Ok(pkg) = pkg {
+    pub fn dependencies<'a>(&'a self, package: &str) -> anyhow::Result<Vec<Dependency<'a>>> {
+        if let Ok(pkg) = self.syncdbs().pkg(package) {
+            return self.pkg_deps(pkg);
+        }
+        // Installed foreign (AUR) package
+        if let Ok(pkg) = self.localdb().pkg(package) {
             return self.pkg_deps(pkg);
         }
         if let Ok(group) = self.group(package) {
-            return Ok(group.packages().into_iter().collect());
+            return Ok(group.packages().into_iter().map(Dependency::Pkg).collect());
+        }
+        if let Some(srcinfo) = srcinfo::find(package)? {
+            return self.srcinfo_deps(&srcinfo);
         }
         bail!("{package}: failed to define package type");
     }
 
-    pub fn recursive_dependencies<'a>(&'a self, package: &str) -> Vec<&'a Package> {
-        // We now doesn't support dependencies finding for aur packages so we just ignore errors
+    pub fn recursive_dependencies<'a>(&'a self, package: &str) -> Vec<Dependency<'a>> {
+        // Not installed AUR packages without paru clone can't be resolved so we just ignore errors
         let Ok(mut scan) = self.dependencies(package) else {
             return Vec::new();
         };
-        let mut deps: Vec<&Package> = Vec::new();
+        let mut deps: Vec<Dependency> = Vec::new();
         while let Some(dep) = scan.pop() {
             if deps.iter().any(|d| d.name() == dep.name()) {
                 continue;
             }
-            if let Ok(deps) = self.pkg_deps(dep) {
+            if let Ok(deps) = self.dep_deps(&dep) {
                 scan.extend(deps);
             }
             deps.push(dep);
@@ -93,16 +189,57 @@ impl PacrsAlpm {
         deps
     }
 
-    fn pkg_deps(&self, pkg: &Package) -> anyhow::Result<Vec<&Package>> {
-        let deps = pkg.depends();
-        let mut res = Vec::with_capacity(deps.len());
-        for dep in deps {
-            let satisfier = self.syncdbs().find_satisfier(dep.name()).with_context(|| {
-                anyhow!("{}: failed to find satisfier for the package", dep.name())
-            })?;
-            res.push(satisfier);
+    pub fn dep_deps<'a>(&'a self, dep: &Dependency<'a>) -> anyhow::Result<Vec<Dependency<'a>>> {
+        match dep {
+            Dependency::Pkg(pkg) => self.pkg_deps(pkg),
+            Dependency::Aur(srcinfo) => self.srcinfo_deps(srcinfo),
+        }
+    }
+
+    fn pkg_deps<'a>(&'a self, pkg: &Package) -> anyhow::Result<Vec<Dependency<'a>>> {
+        pkg.depends()
+            .into_iter()
+            .map(|dep| self.find_satisfier(dep.name()))
+            .collect()
+    }
+
+    /// Optional dependencies of the package. Unresolvable ones are skipped
+    pub fn optional_deps<'a>(&'a self, pkg: &Package) -> Vec<Dependency<'a>> {
+        pkg.optdepends()
+            .into_iter()
+            .filter_map(|dep| self.find_satisfier(dep.name()).ok())
+            .collect()
+    }
+
+    /// Find package in sync databases or among installed packages
+    pub fn find_pkg<'a>(&'a self, package: &str) -> Option<&'a Package> {
+        self.syncdbs()
+            .pkg(package)
+            .or_else(|_| self.localdb().pkg(package))
+            .ok()
+    }
+
+    fn srcinfo_deps<'a>(&'a self, srcinfo: &Srcinfo) -> anyhow::Result<Vec<Dependency<'a>>> {
+        srcinfo
+            .depends
+            .iter()
+            .map(|dep| self.find_satisfier(dep))
+            .collect()
+    }
+
+    /// Find package which satisfies the depstring in sync databases, then among installed
+    /// packages and then among AUR packages cloned by paru
+    fn find_satisfier<'a>(&'a self, depstring: &str) -> anyhow::Result<Dependency<'a>> {
+        if let Some(pkg) = self.syncdbs().find_satisfier(depstring) {
+            return Ok(Dependency::Pkg(pkg));
+        }
+        if let Some(pkg) = self.localdb().pkgs().find_satisfier(depstring) {
+            return Ok(Dependency::Pkg(pkg));
         }
-        Ok(res)
+        let name = srcinfo::dep_name(depstring);
+        let srcinfo = srcinfo::find(name)?
+            .with_context(|| anyhow!("{name}: failed to find satisfier for the package"))?;
+        Ok(Dependency::Aur(srcinfo))
     }
 
     fn group<'a>(&'a self, group: &str) -> anyhow::Result<&'a Group> {
@@ -125,3 +262,238 @@ impl PacrsAlpm {
             .with_context(|| format!("{package}: fackage not found"))
     }
 }
+
+/// Package installed as dependency on which no package depends (even optionally)
+fn is_orphaned(pkg: &Package) -> bool {
+    pkg.reason() == PackageReason::Depend
+        && pkg.required_by().is_empty()
+        && pkg.optional_for().is_empty()
+}
+
+/// Databases with given packages for tests
+#[cfg(test)]
+pub mod fixture {
+    use std::path::Path;
+
+    use alpm::SigLevel;
+    use fs_err as fs;
+
+    use super::PacrsAlpm;
+
+    /// Installed package of version "1.0-1"
+    pub struct FixturePkg {
+        pub name: &'static str,
+        /// Installed as dependency
+        pub depend: bool,
+        pub depends: &'static [&'static str],
+        pub optdepends: &'static [&'static str],
+        /// Present in the `core` sync database
+        pub in_repo: bool,
+    }
+
+    impl FixturePkg {
+        /// Explicitly installed package from the repo without dependencies
+        pub const fn new(name: &'static str) -> Self {
+            Self {
+                name,
+                depend: false,
+                depends: &[],
+                optdepends: &[],
+                in_repo: true,
+            }
+        }
+
+        fn desc(&self) -> String {
+            let mut sections = vec![
+                format!("%NAME%\n{}\n", self.name),
+                "%VERSION%\n1.0-1\n".to_owned(),
+            ];
+            if self.depend {
+                sections.push("%REASON%\n1\n".to_owned());
+            }
+            if !self.depends.is_empty() {
+                sections.push(format!("%DEPENDS%\n{}\n", self.depends.join("\n")));
+            }
+            if !self.optdepends.is_empty() {
+                sections.push(format!("%OPTDEPENDS%\n{}\n", self.optdepends.join("\n")));
+            }
+            sections.join("\n") + "\n"
+        }
+    }
+
+    /// Local database in `dir` with `pkgs` installed and `core` sync database with those of them
+    /// which are in the repo
+    pub fn alpm(dir: &Path, pkgs: &[FixturePkg]) -> PacrsAlpm {
+        let dbpath = dir.join("db");
+        let local = dbpath.join("local");
+        fs::create_dir_all(&local).unwrap();
+        fs::write(local.join("ALPM_DB_VERSION"), "9\n").unwrap();
+        let mut sync_files = Vec::new();
+        for pkg in pkgs {
+            let pkg_dir = format!("{}-1.0-1", pkg.name);
+            fs::create_dir(local.join(&pkg_dir)).unwrap();
+            fs::write(local.join(&pkg_dir).join("desc"), pkg.desc()).unwrap();
+            if pkg.in_repo {
+                sync_files.push((format!("{pkg_dir}/desc"), pkg.desc()));
+            }
+        }
+        fs::create_dir(dbpath.join("sync")).unwrap();
+        fs::write(dbpath.join("sync/core.db"), tar(&sync_files)).unwrap();
+        let alpm = PacrsAlpm::with_paths(dir, &dbpath).unwrap();
+        alpm.register_syncdb("core", SigLevel::NONE).unwrap();
+        alpm
+    }
+
+    /// Uncompressed tar archive, which is enough for libalpm to read a sync database
+    fn tar(files: &[(String, String)]) -> Vec<u8> {
+        let mut archive = Vec::new();
+        for (name, content) in files {
+            let mut header = [0; 512];
+            header[..name.len()].copy_from_slice(name.as_bytes());
+            header[100..107].copy_from_slice(b"0000644");
+            header[108..115].copy_from_slice(b"0000000");
+            header[116..123].copy_from_slice(b"0000000");
+            header[124..135].copy_from_slice(format!("{:011o}", content.len()).as_bytes());
+            header[136..147].copy_from_slice(b"00000000000");
+            header[156] = b'0';
+            header[257..263].copy_from_slice(b"ustar\0");
+            header[263..265].copy_from_slice(b"00");
+            // Checksum is calculated with the checksum field filled with spaces
+            header[148..156].fill(b' ');
+            let checksum: u32 = header.iter().copied().map(u32::from).sum();
+            header[148..155].copy_from_slice(format!("{checksum:06o}\0").as_bytes());
+            archive.extend_from_slice(&header);
+            archive.extend_from_slice(content.as_bytes());
+            archive.resize(archive.len().next_multiple_of(512), 0);
+        }
+        archive.resize(archive.len() + 1024, 0);
+        archive
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fs_err as fs;
+
+    use super::fixture::{self, FixturePkg};
+    use super::*;
+    use crate::utils::test_dir;
+
+    const PKGS: &[FixturePkg] = &[
+        FixturePkg {
+            depends: &["lib"],
+            optdepends: &["opt: optional feature"],
+            ..FixturePkg::new("app")
+        },
+        FixturePkg {
+            depend: true,
+            ..FixturePkg::new("lib")
+        },
+        FixturePkg {
+            depend: true,
+            ..FixturePkg::new("opt")
+        },
+        FixturePkg {
+            depend: true,
+            ..FixturePkg::new("orphan")
+        },
+        FixturePkg {
+            depends: &["aurdep-required"],
+            in_repo: false,
+            ..FixturePkg::new("aurtool")
+        },
+        FixturePkg {
+            depend: true,
+            in_repo: false,
+            ..FixturePkg::new("aurdep")
+        },
+        FixturePkg {
+            depend: true,
+            in_repo: false,
+            ..FixturePkg::new("aurdep-required")
+        },
+    ];
+
+    fn names(alpm: &PacrsAlpm, filter: PkgFilter) -> Vec<&str> {
+        let mut names: Vec<&str> = alpm
+            .filtered_pkgs(filter)
+            .into_iter()
+            .map(|pkg| pkg.name())
+            .collect();
+        names.sort_unstable();
+        names
+    }
+    #[test]
+    fn single_filters() {
+        let dir = test_dir("filters");
+        let alpm = fixture::alpm(&dir, PKGS);
+        assert_eq!(names(&alpm, PkgFilter::default()).len(), PKGS.len());
+        let explicit = PkgFilter {
+            explicit: true,
+            ..PkgFilter::default()
+        };
+        assert_eq!(names(&alpm, explicit), ["app", "aurtool"]);
+        let deps = PkgFilter {
+            deps: true,
+            ..PkgFilter::default()
+        };
+        assert_eq!(
+            names(&alpm, deps),
+            ["aurdep", "aurdep-required", "lib", "opt", "orphan"]
+        );
+        let foreign = PkgFilter {
+            foreign: true,
+            ..PkgFilter::default()
+        };
+        assert_eq!(
+            names(&alpm, foreign),
+            ["aurdep", "aurdep-required", "aurtool"]
+        );
+        // Neither required nor optional dependencies are orphaned
+        let orphaned = PkgFilter {
+            orphaned: true,
+            ..PkgFilter::default()
+        };
+        assert_eq!(names(&alpm, orphaned), ["aurdep", "orphan"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn combined_filters() {
+        let dir = test_dir("combined-filters");
+        let alpm = fixture::alpm(&dir, PKGS);
+        let foreign_explicit = PkgFilter {
+            foreign: true,
+            explicit: true,
+            ..PkgFilter::default()
+        };
+        assert_eq!(names(&alpm, foreign_explicit), ["aurtool"]);
+        let foreign_orphaned_deps = PkgFilter {
+            orphaned: true,
+            foreign: true,
+            deps: true,
+            ..PkgFilter::default()
+        };
+        assert_eq!(names(&alpm, foreign_orphaned_deps), ["aurdep"]);
+        let foreign_deps = PkgFilter {
+            foreign: true,
+            deps: true,
+            ..PkgFilter::default()
+        };
+        assert_eq!(names(&alpm, foreign_deps), ["aurdep", "aurdep-required"]);
+        let explicit_deps = PkgFilter {
+            explicit: true,
+            deps: true,
+            ..PkgFilter::default()
+        };
+        assert!(names(&alpm, explicit_deps).is_empty());
+        let orphaned_explicit = PkgFilter {
+            orphaned: true,
+            explicit: true,
+            ..PkgFilter::default()
+        };
+        assert!(names(&alpm, orphaned_explicit).is_empty());
+        assert_eq!(alpm.orphaned_pkgs().len(), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
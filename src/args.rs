@@ -1,15 +1,41 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
-use clap::{Parser, ValueEnum};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::{Parser, Subcommand, ValueEnum};
 
 #[derive(Parser)]
 #[command(version = clap::crate_version!(), about = clap::crate_description!())]
-pub enum Args {
+pub struct Args {
+    /// Output format of listing commands
+    #[clap(long, global = true, value_name = "FORMAT", default_value = "text")]
+    pub output: OutputFormat,
+    /// AUR helper to use. By default the first installed of paru, yay and pikaur is used
+    #[clap(long, global = true, value_name = "HELPER")]
+    pub helper: Option<AurHelper>,
+    /// Colored output. Overrides `color` of the config
+    #[clap(long, global = true, value_name = "WHEN")]
+    pub color: Option<ColorWhen>,
+    #[clap(subcommand)]
+    pub command: Commands,
+}
+
+#[derive(Subcommand)]
+pub enum Commands {
     /// Install packages
     #[clap(visible_alias = "in")]
     Install {
         #[clap(required = true)]
         packages: Vec<String>,
+        /// Reinstall packages from the cache without checking for updates in the repo
+        #[clap(long)]
+        from_cache: bool,
+        /// Only show what would be done without touching the system
+        #[clap(long, short = 'n', conflicts_with = "from_cache")]
+        dry_run: bool,
+        #[clap(flatten)]
+        refresh_group: RefreshGroup,
     },
     /// Remove (uninstall) packages
     #[clap(visible_alias = "rm")]
@@ -19,6 +45,9 @@ pub enum Args {
         /// Automatically remove dependencies which become unneeded after removal of requested packages
         #[clap(long, short, short = 'u')]
         clean_deps: bool,
+        /// Only show what would be done without touching the system
+        #[clap(long, short = 'n')]
+        dry_run: bool,
     },
     /// Remove unneeded packages
     #[clap(visible_alias = "ar")]
@@ -34,6 +63,9 @@ pub enum Args {
         /// Show less inforamation
         #[clap(long, short)]
         quiet: bool,
+        /// Only show what would be done without touching the system
+        #[clap(long, short = 'n')]
+        dry_run: bool,
     },
     /// Display list of all available packages
     #[clap(visible_alias = "pa")]
@@ -67,8 +99,58 @@ pub enum Args {
         #[clap(required = true)]
         package: String,
         /// Display list of recursive dependencies
-        #[clap(long, short)]
+        #[clap(long, short, conflicts_with = "tree")]
         recursive_deps: bool,
+        /// Display dependency tree
+        #[clap(long, short)]
+        tree: bool,
+        /// Maximum depth of the dependency tree
+        #[clap(long, short, value_name = "NUM", requires = "tree")]
+        depth: Option<usize>,
+        /// Show optional dependencies in the dependency tree
+        #[clap(long, short, requires = "tree")]
+        optional: bool,
+        /// Show packages which depend on the package instead of its dependencies
+        #[clap(long, requires = "tree")]
+        reverse: bool,
+    },
+    /// Explain why the package is installed
+    #[clap(visible_alias = "wh")]
+    Why {
+        #[clap(required = true)]
+        package: String,
+    },
+    /// Install older version of the package from the cache
+    #[clap(visible_alias = "dg")]
+    Downgrade {
+        package: String,
+        /// Version to install. If it's omitted cached versions are offered to choose from
+        version: Option<String>,
+        /// Ignore the package by 'pacrs update' until the end of the session
+        #[clap(long, short)]
+        ignore: bool,
+    },
+    /// Display history of package changes from pacman log
+    #[clap(visible_alias = "hi")]
+    History {
+        /// Show only changes of the package
+        package: Option<String>,
+        /// Show only given actions
+        #[clap(long, short, value_name = "ACTION", value_delimiter = ',')]
+        action: Vec<HistoryAction>,
+        /// Show changes since given date: "YYYY-MM-DD", "YYYY-MM-DD HH:MM", "today" or "yesterday"
+        #[clap(long, short, value_name = "DATE")]
+        since: Option<String>,
+        /// Show changes until given date. The whole day is included if time is omitted
+        #[clap(long, short, value_name = "DATE")]
+        until: Option<String>,
+    },
+    /// Revert the last transactions using archives from the cache
+    #[clap(visible_alias = "rb")]
+    Rollback {
+        /// Number of transactions to revert
+        #[clap(long, short, value_name = "N", default_value_t = 1)]
+        transactions: usize,
     },
     /// Display list available updates
     #[clap(visible_alias = "lu")]
@@ -76,6 +158,8 @@ pub enum Args {
         /// Verbose output
         #[clap(long, short = 'V')]
         verbose: bool,
+        #[clap(flatten)]
+        refresh_group: RefreshGroup,
     },
     /// Query the file database
     #[clap(visible_alias = "fl")]
@@ -102,19 +186,41 @@ pub enum Args {
         /// Clean AUR cache
         #[clap(long, short, conflicts_with = "uninstalled")]
         aur: bool,
-        /// Keep "num" of each package in the cache
-        #[clap(
-            long,
-            short,
-            value_name = "NUM",
-            default_value = "0",
-            conflicts_with = "uninstalled",
-            conflicts_with = "aur"
-        )]
-        keep: u8,
+        /// Keep "num" of each package in each cache directory besides the installed version. By
+        /// default the value from config is used or entire cache is cleaned
+        #[clap(long, short, value_name = "NUM", conflicts_with = "uninstalled")]
+        keep: Option<u8>,
+        /// Remove only archives older than given age, e.g. "30d", "12h" or "2w".
+        /// Installed versions are always kept
+        #[clap(long, value_name = "AGE", value_parser = parse_age, conflicts_with_all = ["uninstalled", "aur"])]
+        older_than: Option<Duration>,
+        /// Remove the oldest archives until the cache fits in given size, e.g. "5G" or "500M".
+        /// Installed versions are always kept
+        #[clap(long, value_name = "SIZE", value_parser = parse_size, conflicts_with_all = ["uninstalled", "aur"])]
+        max_size: Option<u64>,
         /// Only show remove candidates without actually removing
         #[clap(long, short, conflicts_with = "aur", conflicts_with = "uninstalled")]
         show_remove_candidates: bool,
+        /// Use given cache directory instead of directories from pacman.conf
+        #[clap(long, value_name = "DIR", conflicts_with = "aur")]
+        cache_dir: Option<PathBuf>,
+        /// Check integrity and signatures of cached archives and offer to remove damaged ones
+        #[clap(
+            long,
+            conflicts_with_all = [
+                "uninstalled", "aur", "keep", "older_than", "max_size", "show_remove_candidates"
+            ]
+        )]
+        verify: bool,
+        /// Remove leftovers of interrupted downloads
+        #[clap(
+            long,
+            conflicts_with_all = [
+                "uninstalled", "aur", "keep", "older_than", "max_size", "show_remove_candidates",
+                "verify"
+            ]
+        )]
+        partial: bool,
     },
     /// Mark packages
     #[clap(visible_alias = "mr")]
@@ -143,6 +249,42 @@ pub enum Args {
     },
 }
 
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Human readable output
+    Text,
+    Json,
+    /// Tab separated values with a header line
+    Tsv,
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+pub enum ColorWhen {
+    /// Use colors if terminal supports them
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryAction {
+    Installed,
+    Upgraded,
+    Removed,
+    Downgraded,
+    Reinstalled,
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AurHelper {
+    Paru,
+    Yay,
+    Pikaur,
+    /// Use only pacman
+    #[value(alias = "none")]
+    Pacman,
+}
+
 #[derive(ValueEnum, Clone, Copy)]
 pub enum PsSortBy {
     Pid,
@@ -160,3 +302,72 @@ pub struct MarkGroup {
     #[clap(long, short)]
     pub dependency: bool,
 }
+
+/// How to treat the temp sync databases which are used for checking updates
+#[derive(Debug, Parser)]
+#[group(multiple = false)]
+pub struct RefreshGroup {
+    /// Update the temp sync databases even if they are fresh
+    #[clap(long)]
+    pub refresh: bool,
+    /// Don't update the temp sync databases if they were downloaded before
+    #[clap(long)]
+    pub no_refresh: bool,
+}
+
+/// Parse age like "30d", "12h" or "2w". Days are used if unit is omitted
+///
+/// # Errors
+/// On unknown unit or too big age
+pub fn parse_age(value: &str) -> Result<Duration, String> {
+    let (number, unit) = split_number(value)?;
+    let seconds = match unit {
+        "h" => 60 * 60,
+        "d" | "" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("unknown unit '{unit}', expected 'h', 'd' or 'w'")),
+    };
+    number
+        .checked_mul(seconds)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("age '{value}' is too big"))
+}
+
+/// Parse size like "5G", "500MiB" or "1024". Units are binary, so "1K" is 1024 bytes
+///
+/// # Errors
+/// On unknown unit or too big size
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let (number, unit) = split_number(value)?;
+    let unit = unit.to_ascii_uppercase();
+    let unit = unit
+        .strip_suffix("IB")
+        .or_else(|| unit.strip_suffix('B'))
+        .unwrap_or(&unit);
+    let multiplier: u64 = match unit {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => {
+            return Err(format!(
+                "unknown unit '{unit}', expected 'K', 'M', 'G' or 'T'"
+            ));
+        }
+    };
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("size '{value}' is too big"))
+}
+
+fn split_number(value: &str) -> Result<(u64, &str), String> {
+    let unit_start = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(unit_start);
+    let number = number
+        .parse()
+        .map_err(|_| format!("'{value}' doesn't start with a number"))?;
+    Ok((number, unit))
+}
@@ -0,0 +1,146 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Cleaning of AUR helper cache which contains clones of packages with downloaded sources and
+//! built archives, which some helpers keep in a separate directory
+
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use bytesize::ByteSize;
+use fs_err as fs;
+use owo_colors::{OwoColorize, Stream::Stdout};
+
+use crate::alpm::PacrsAlpm;
+use crate::clean::{self, CacheEntry};
+use crate::cli::{Answer, Cli};
+
+const SOURCE_ARCHIVE_EXTENSIONS: [&str; 6] =
+    [".tar.gz", ".tar.xz", ".tar.bz2", ".tar.zst", ".tgz", ".zip"];
+
+#[derive(Default)]
+struct AurCache {
+    /// Built packages
+    archives: Vec<CacheEntry>,
+    /// Downloaded source archives of installed packages
+    sources: Vec<PathBuf>,
+    /// Clones of packages which are no longer installed
+    stale_clones: Vec<PathBuf>,
+}
+
+/// Keep "keep" newest built packages of AUR helper and offer to remove downloaded sources and
+/// clones of uninstalled packages. Built packages are searched in the clones and in `pkg_dir`
+pub fn clean(clone_dir: &Path, pkg_dir: Option<&Path>, keep: u8) -> anyhow::Result<()> {
+    let alpm = PacrsAlpm::new()?;
+    let mut cache = scan(&alpm, clone_dir)?;
+    if let Some(pkg_dir) = pkg_dir.filter(|dir| dir.exists()) {
+        let regex = clean::cache_entry_regex()?;
+        cache
+            .archives
+            .extend(clean::read_archives(pkg_dir, &regex)?);
+    }
+    let mut remove_candidates = clean::keep_newest(cache.archives, keep);
+    clean::protect_installed(&alpm, &mut remove_candidates);
+    clean::confirm_and_remove(remove_candidates)?;
+    remove_sources(&cache.sources)?;
+    remove_stale_clones(&cache.stale_clones)?;
+    Ok(())
+}
+
+fn scan(alpm: &PacrsAlpm, clone_dir: &Path) -> anyhow::Result<AurCache> {
+    let mut cache = AurCache::default();
+    if !clone_dir.exists() {
+        return Ok(cache);
+    }
+    let regex = clean::cache_entry_regex()?;
+    for entry in fs::read_dir(clone_dir)? {
+        let path = entry?.path();
+        if !path.is_dir() {
+            continue;
+        }
+        let pkgbase = path.file_name().unwrap_or_default().to_string_lossy();
+        if !is_installed_pkgbase(alpm, &pkgbase) {
+            cache.stale_clones.push(path);
+            continue;
+        }
+        cache.archives.extend(clean::read_archives(&path, &regex)?);
+        cache.sources.extend(source_archives(&path)?);
+    }
+    Ok(cache)
+}
+
+fn is_installed_pkgbase(alpm: &PacrsAlpm, pkgbase: &str) -> bool {
+    alpm.localdb()
+        .pkgs()
+        .iter()
+        .any(|pkg| pkg.name() == pkgbase || pkg.base() == Some(pkgbase))
+}
+
+fn source_archives(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut sources = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let is_source = !file_name.contains(".pkg.tar")
+            && SOURCE_ARCHIVE_EXTENSIONS
+                .iter()
+                .any(|ext| file_name.ends_with(ext));
+        if is_source && path.is_file() {
+            sources.push(path);
+        }
+    }
+    Ok(sources)
+}
+
+fn remove_sources(sources: &[PathBuf]) -> anyhow::Result<()> {
+    if sources.is_empty() {
+        return Ok(());
+    }
+    let mut total_size = 0;
+    for source in sources {
+        total_size += source.metadata()?.size();
+    }
+    let prompt = "==>".if_supports_color(Stdout, |t| t.green());
+    println!(
+        "{}",
+        format!(
+            "{prompt} {} source archives using {} of disk",
+            sources.len(),
+            ByteSize::b(total_size).if_supports_color(Stdout, |t| t.bright_blue())
+        )
+        .if_supports_color(Stdout, |t| t.bold())
+    );
+    let mut cli = Cli::new();
+    let answer = cli.confirm("Remove source archives?", Answer::No)?;
+    if !answer.as_bool() {
+        return Ok(());
+    }
+    let mut removed_size = 0;
+    for source in sources {
+        removed_size += clean::remove_file(source)?;
+    }
+    clean::print_removed_stats(sources.len(), sources.len(), removed_size);
+    Ok(())
+}
+
+fn remove_stale_clones(clones: &[PathBuf]) -> anyhow::Result<()> {
+    if clones.is_empty() {
+        return Ok(());
+    }
+    println!("Clones of packages which are no longer installed:");
+    for clone in clones {
+        println!(
+            "    {}",
+            clone.file_name().unwrap_or_default().to_string_lossy()
+        );
+    }
+    let mut cli = Cli::new();
+    let answer = cli.confirm("Remove these clones?", Answer::No)?;
+    if !answer.as_bool() {
+        return Ok(());
+    }
+    for clone in clones {
+        log::info!("Removing directory: {}", clone.display());
+        fs::remove_dir_all(clone)?;
+    }
+    Ok(())
+}
@@ -1,20 +1,29 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::fmt;
 use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 
-use anyhow::Context;
+use anyhow::{Context, bail};
 use bytesize::ByteSize;
+use derive_more::Display;
 use fs_err as fs;
 use owo_colors::OwoColorize;
+use owo_colors::Stream::{Stderr, Stdout};
 use regex::Regex;
+use serde::Serialize;
 
+use crate::alpm::{PacrsAlpm, pacmanconf};
 use crate::cli::{Answer, Cli};
+use crate::output::Record;
 
-const PACMAN_CACHE_PATH: &str = "/var/cache/pacman/pkg";
-
-const CACHE_ENTRY_REGEX: &str = r"(?<name>[\w\-\d\.+]+)-(?<version>[\w\d\.\-:+]*)-(?<subversion>[\w\d\._]+)-(?<arch>[\d\w_]+)\.(?<ext>[\w\.]+)";
+const PARTIAL_EXTENSION: &str = "part";
+/// Prefix of temporary directories which pacman creates for downloads
+const DOWNLOAD_DIR_PREFIX: &str = "download-";
+const CACHE_ENTRY_REGEX: &str = r"^(?<name>[\w\-\d\.+]+)-(?<version>[\w\d\.\-:+]*)-(?<subversion>[\w\d\._]+)-(?<arch>[\d\w_]+)\.(?<ext>[\w\.]+)$";
 
 #[derive(PartialEq, Eq, Clone, Debug)]
 pub struct CacheEntry {
@@ -23,10 +32,68 @@ pub struct CacheEntry {
     subversion: String,
     arch: String,
     ext: String,
+    dir: PathBuf,
+}
+
+/// Content of cache directories
+#[derive(Default)]
+struct Cache {
+    archives: Vec<CacheEntry>,
+    /// Files and directories which are neither package archives nor their signatures
+    unknown: Vec<PathBuf>,
+}
+
+/// Rules for selecting remove candidates. An archive is removed only if all given rules allow it
+#[derive(Default, Clone, Copy)]
+pub struct CleanPolicy {
+    /// Number of newest versions of each package to keep
+    pub keep: u8,
+    /// Remove only archives older than this
+    pub older_than: Option<Duration>,
+    /// Remove the oldest archives until the cache fits in this size
+    pub max_size: Option<u64>,
+}
+
+#[derive(Default)]
+struct PartialDownloads {
+    /// `.part` files
+    files: Vec<PathBuf>,
+    /// Temporary download directories which contain nothing but `.part` files
+    dirs: Vec<PathBuf>,
+}
+
+#[derive(Serialize)]
+pub struct CacheRecord {
+    path: PathBuf,
+    name: String,
+    version: String,
+    size: u64,
+    installed: Option<InstalledStatus>,
+}
+
+impl Record for CacheRecord {
+    const HEADERS: &'static [&'static str] = &["path", "name", "version", "size", "installed"];
+}
+
+/// Version of cached archive relative to the installed version of the package
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Display)]
+#[serde(rename_all = "kebab-case")]
+pub enum InstalledStatus {
+    #[display("installed")]
+    Installed,
+    #[display("newer than installed")]
+    Newer,
+    #[display("older than installed")]
+    Older,
 }
 
-pub fn clean(keep: u8) -> anyhow::Result<()> {
-    let remove_candidates = remove_candidates(keep)?;
+pub fn clean(cache_dirs: &[PathBuf], policy: CleanPolicy) -> anyhow::Result<()> {
+    let remove_candidates = remove_candidates(cache_dirs, policy)?;
+    confirm_and_remove(remove_candidates)
+}
+
+/// Show statistics of remove candidates and remove them with their signatures if user agree
+pub fn confirm_and_remove(remove_candidates: Vec<CacheEntry>) -> anyhow::Result<()> {
     let candidates_count = remove_candidates.len();
     if candidates_count == 0 {
         println!("No candidates to remove");
@@ -39,23 +106,33 @@ pub fn clean(keep: u8) -> anyhow::Result<()> {
         return Ok(());
     }
     let mut total_size = 0;
+    let mut files_count = 0;
     for entry in remove_candidates {
         let path = entry.path();
-        let metadata = entry.path().metadata()?;
-        total_size += metadata.size();
-        let path_str = path.to_string_lossy();
-        log::info!("Removing file: {path_str}");
-        fs::remove_file(&path)?;
-        let sig_path = format!("{path_str}.sig");
-        let metadata = Path::new(&sig_path).metadata()?;
-        total_size += metadata.size();
-        log::info!("Removing file: {sig_path}");
-        fs::remove_file(sig_path)?;
-    }
-    let files_count = candidates_count * 2;
+        total_size += remove_file(&path)?;
+        files_count += 1;
+        let sig_path = entry.sig_path();
+        if sig_path.exists() {
+            total_size += remove_file(&sig_path)?;
+            files_count += 1;
+        }
+    }
+    print_removed_stats(candidates_count, files_count, total_size);
+    Ok(())
+}
+
+/// Remove file and return its size
+pub fn remove_file(path: &Path) -> anyhow::Result<u64> {
+    let size = path.metadata()?.size();
+    log::info!("Removing file: {}", path.display());
+    fs::remove_file(path)?;
+    Ok(size)
+}
+
+pub fn print_removed_stats(candidates_count: usize, files_count: usize, total_size: u64) {
     let total_size = ByteSize::b(total_size).to_string();
-    let total_size = total_size.bright_blue();
-    let prompt = "==>".green();
+    let total_size = total_size.if_supports_color(Stdout, |t| t.bright_blue());
+    let prompt = "==>".if_supports_color(Stdout, |t| t.green());
     println!(
         "{}",
         format!(
@@ -63,86 +140,424 @@ pub fn clean(keep: u8) -> anyhow::Result<()> {
             {files_count} files removed. \
             {total_size} disk space saved"
         )
-        .bold()
+        .if_supports_color(Stdout, |t| t.bold())
     );
-    Ok(())
 }
 
 pub fn show_cache(cache: &[CacheEntry], only_stats: bool) -> anyhow::Result<()> {
+    let alpm = (!only_stats).then(PacrsAlpm::new).transpose()?;
     let mut total_size = 0;
+    let mut dir_stats: BTreeMap<&Path, (usize, u64)> = BTreeMap::new();
     for entry in cache {
-        let metadata = entry.path().metadata()?;
-        total_size += metadata.size();
-        if !only_stats {
-            println!("{entry}");
+        let size = entry.size_with_sig()?;
+        total_size += size;
+        let (dir_count, dir_size) = dir_stats.entry(&entry.dir).or_default();
+        *dir_count += 1;
+        *dir_size += size;
+        if let Some(alpm) = &alpm {
+            match entry.installed_status(alpm) {
+                Some(status) => println!(
+                    "{entry} {}",
+                    format!("[{status}]").if_supports_color(Stdout, |t| t.cyan())
+                ),
+                None => println!("{entry}"),
+            }
+        }
+    }
+    if dir_stats.len() > 1 {
+        for (dir, (count, size)) in dir_stats {
+            let size = ByteSize::b(size).to_string();
+            println!(
+                "    {}: {count} candidates using {}",
+                dir.display(),
+                size.if_supports_color(Stdout, |t| t.bright_blue())
+            );
         }
     }
     let candidates_count = cache.len();
     let total_size = ByteSize::b(total_size).to_string();
-    let total_size = total_size.bright_blue();
-    let prompt = "==>".green();
+    let total_size = total_size.if_supports_color(Stdout, |t| t.bright_blue());
+    let prompt = "==>".if_supports_color(Stdout, |t| t.green());
     println!(
         "{}",
-        format!("{prompt} {candidates_count} candidates using {total_size} of disk").bold()
+        format!("{prompt} {candidates_count} candidates using {total_size} of disk")
+            .if_supports_color(Stdout, |t| t.bold())
     );
     Ok(())
 }
 
-pub fn remove_candidates(keep: u8) -> anyhow::Result<Vec<CacheEntry>> {
-    let mut cache = read_cache()?;
-    cache.sort();
-    cache.reverse();
-    let mut remove_candidates = Vec::new();
-    let mut i = 0;
-    while i < cache.len() {
-        let keeped = i + keep as usize;
-        if keeped > cache.len() {
+pub fn cache_records(cache: &[CacheEntry]) -> anyhow::Result<Vec<CacheRecord>> {
+    let alpm = PacrsAlpm::new()?;
+    let mut records = Vec::with_capacity(cache.len());
+    for entry in cache {
+        records.push(CacheRecord {
+            path: entry.path(),
+            name: entry.pkg_name.clone(),
+            version: entry.full_version(),
+            size: entry.size_with_sig()?,
+            installed: entry.installed_status(&alpm),
+        });
+    }
+    Ok(records)
+}
+
+pub fn remove_candidates(
+    cache_dirs: &[PathBuf],
+    policy: CleanPolicy,
+) -> anyhow::Result<Vec<CacheEntry>> {
+    let cache = read_cache(cache_dirs)?;
+    if policy.is_clean_all() {
+        return Ok(cache);
+    }
+    let mut cache_size = 0;
+    for entry in &cache {
+        cache_size += entry.size_with_sig()?;
+    }
+    let alpm = PacrsAlpm::new()?;
+    let mut candidates = keep_newest(cache, policy.keep);
+    protect_installed(&alpm, &mut candidates);
+    if let Some(age) = policy.older_than {
+        candidates = older_than(candidates, age, SystemTime::now())?;
+    }
+    if let Some(max_size) = policy.max_size {
+        candidates = fit_in_size(candidates, cache_size, max_size)?;
+    }
+    Ok(candidates)
+}
+
+/// Keep candidates which were modified more than `age` before `now`
+fn older_than(
+    candidates: Vec<CacheEntry>,
+    age: Duration,
+    now: SystemTime,
+) -> anyhow::Result<Vec<CacheEntry>> {
+    let mut old = Vec::with_capacity(candidates.len());
+    for entry in candidates {
+        let modified = entry.path().metadata()?.modified()?;
+        if now.duration_since(modified).unwrap_or_default() > age {
+            old.push(entry);
+        }
+    }
+    Ok(old)
+}
+
+/// Choose the oldest candidates which have to be removed to reduce the cache to `max_size`
+fn fit_in_size(
+    candidates: Vec<CacheEntry>,
+    mut cache_size: u64,
+    max_size: u64,
+) -> anyhow::Result<Vec<CacheEntry>> {
+    let mut candidates_by_age = Vec::with_capacity(candidates.len());
+    for entry in candidates {
+        let modified = entry.path().metadata()?.modified()?;
+        // Signature is removed together with the archive
+        candidates_by_age.push((modified, entry.size_with_sig()?, entry));
+    }
+    candidates_by_age.sort_by_key(|(modified, _, _)| *modified);
+    let mut fitted = Vec::new();
+    for (_, size, entry) in candidates_by_age {
+        if cache_size <= max_size {
             break;
         }
-        let base = &cache[i].pkg_name;
-        while i < cache.len() && base == &cache[i].pkg_name {
-            remove_candidates.push(cache[i].clone());
-            i += 1;
+        cache_size = cache_size.saturating_sub(size);
+        fitted.push(entry);
+    }
+    Ok(fitted)
+}
+
+/// Exclude archives of installed versions from remove candidates
+pub fn protect_installed(alpm: &PacrsAlpm, candidates: &mut Vec<CacheEntry>) {
+    candidates.retain(|entry| entry.installed_status(alpm) != Some(InstalledStatus::Installed));
+}
+
+/// Remove candidates which remain after keeping "keep" newest versions of each package. Each cache
+/// directory keeps its own versions, e.g. a local repo isn't emptied by newer archives in the
+/// pacman cache
+pub fn keep_newest(mut cache: Vec<CacheEntry>, keep: u8) -> Vec<CacheEntry> {
+    // Newest versions of each package go first
+    cache.sort_by(|a, b| a.dir.cmp(&b.dir).then_with(|| b.cmp(a)));
+    cache
+        .chunk_by(|a, b| a.dir == b.dir && a.pkg_name == b.pkg_name)
+        .flat_map(|versions| versions.iter().skip(keep.into()).cloned())
+        .collect()
+}
+
+/// Find the newest cached archive of each package built for one of given architectures
+pub fn newest_cached(
+    cache_dirs: &[PathBuf],
+    packages: &[String],
+    archs: &[String],
+) -> anyhow::Result<Vec<CacheEntry>> {
+    let cache = read_cache(cache_dirs)?;
+    let mut found = Vec::with_capacity(packages.len());
+    let mut not_cached = Vec::new();
+    let mut without_sig = Vec::new();
+    for package in packages {
+        let newest = cache
+            .iter()
+            .filter(|entry| &entry.pkg_name == package && archs.contains(&entry.arch))
+            .max();
+        match newest {
+            None => not_cached.push(package.as_str()),
+            Some(entry) if !entry.sig_path().exists() => without_sig.push(entry.to_string()),
+            Some(entry) => found.push(entry.clone()),
         }
-        i += 1;
     }
-    Ok(remove_candidates)
+    let mut errors = Vec::new();
+    if !not_cached.is_empty() {
+        errors.push(format!(
+            "no cached archive for packages: {}",
+            not_cached.join(", ")
+        ));
+    }
+    if !without_sig.is_empty() {
+        errors.push(format!(
+            "signature not found for archives: {}",
+            without_sig.join(", ")
+        ));
+    }
+    if !errors.is_empty() {
+        bail!(errors.join("\n"));
+    }
+    Ok(found)
 }
 
-fn read_cache() -> anyhow::Result<Vec<CacheEntry>> {
-    let regex = Regex::new(CACHE_ENTRY_REGEX)
-        .context("failed to compile regular expression for cache file names")?;
-    let mut cache = Vec::new();
-    for entry in fs::read_dir(PACMAN_CACHE_PATH)? {
-        let entry = entry?;
-        if let Some(ext) = entry.path().extension()
-            && ext == "sig"
-        {
+/// All archives of the package built for one of given architectures, newest first
+pub fn cached_versions(cache: &[CacheEntry], package: &str, archs: &[String]) -> Vec<CacheEntry> {
+    let mut versions: Vec<CacheEntry> = cache
+        .iter()
+        .filter(|entry| entry.pkg_name == package && archs.contains(&entry.arch))
+        .cloned()
+        .collect();
+    versions.sort();
+    versions.reverse();
+    versions
+}
+
+/// Cache directories from pacman.conf or the given one
+pub fn cache_dirs(cache_dir: Option<PathBuf>) -> anyhow::Result<Vec<PathBuf>> {
+    if let Some(cache_dir) = cache_dir {
+        return Ok(vec![cache_dir]);
+    }
+    let conf = pacmanconf()?;
+    Ok(conf.cache_dir.into_iter().map(PathBuf::from).collect())
+}
+
+pub fn read_cache(cache_dirs: &[PathBuf]) -> anyhow::Result<Vec<CacheEntry>> {
+    let cache = scan_cache(cache_dirs)?;
+    if !cache.unknown.is_empty() {
+        eprintln!(
+            "{}: {} files in the cache are not package archives and were skipped",
+            "Note".if_supports_color(Stderr, |t| t.bright_blue()),
+            cache.unknown.len()
+        );
+    }
+    Ok(cache.archives)
+}
+
+/// Read content of cache directories
+fn scan_cache(cache_dirs: &[PathBuf]) -> anyhow::Result<Cache> {
+    let regex = cache_entry_regex()?;
+    let mut cache = Cache::default();
+    for cache_dir in cache_dirs {
+        if !cache_dir.exists() {
+            eprintln!(
+                "{}: cache directory '{}' doesn't exist",
+                "Warning".if_supports_color(Stderr, |t| t.yellow()),
+                cache_dir.display()
+            );
             continue;
         }
+        let dir_cache = scan_dir(cache_dir, &regex)?;
+        cache.archives.extend(dir_cache.archives);
+        cache.unknown.extend(dir_cache.unknown);
+    }
+    Ok(cache)
+}
+
+/// Read package archives from the directory skipping any other files
+pub fn read_archives(dir: &Path, regex: &Regex) -> anyhow::Result<Vec<CacheEntry>> {
+    Ok(scan_dir(dir, regex)?.archives)
+}
+
+fn scan_dir(dir: &Path, regex: &Regex) -> anyhow::Result<Cache> {
+    let mut cache = Cache::default();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
         let file_name = entry.file_name();
         let file_name = file_name.to_string_lossy();
-        let cache_entry = parse_file_name(file_name.as_ref(), &regex)
-            .context(format!("failed to parse file name '{file_name}'"))?;
-        cache.push(cache_entry);
+        if path.is_dir() {
+            cache.unknown.push(path);
+            continue;
+        }
+        match parse_file_name(&file_name, regex, dir) {
+            Some(cache_entry) if cache_entry.is_pkg_archive() => cache.archives.push(cache_entry),
+            Some(cache_entry) if cache_entry.is_signature() => {}
+            _ => {
+                log::info!("Unknown file in the cache: {}", path.display());
+                cache.unknown.push(path);
+            }
+        }
     }
     Ok(cache)
 }
 
-fn parse_file_name(file_name: &str, regex: &Regex) -> Option<CacheEntry> {
+/// Leftovers of interrupted downloads
+fn partial_downloads(cache_dirs: &[PathBuf]) -> anyhow::Result<PartialDownloads> {
+    let mut partial = PartialDownloads::default();
+    for path in scan_cache(cache_dirs)?.unknown {
+        if is_partial_file(&path) {
+            partial.files.push(path);
+            continue;
+        }
+        let is_download_dir = path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with(DOWNLOAD_DIR_PREFIX));
+        if !path.is_dir() || !is_download_dir {
+            continue;
+        }
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&path)? {
+            files.push(entry?.path());
+        }
+        // Directories with anything except partial downloads are left untouched
+        if files.iter().all(|file| is_partial_file(file)) {
+            partial.files.extend(files);
+            partial.dirs.push(path);
+        }
+    }
+    Ok(partial)
+}
+
+fn is_partial_file(path: &Path) -> bool {
+    path.is_file() && path.extension().is_some_and(|ext| ext == PARTIAL_EXTENSION)
+}
+
+/// Remove leftovers of interrupted downloads if user agree
+pub fn clean_partial(cache_dirs: &[PathBuf]) -> anyhow::Result<()> {
+    let partial = partial_downloads(cache_dirs)?;
+    if partial.files.is_empty() && partial.dirs.is_empty() {
+        println!("No partial downloads to remove");
+        return Ok(());
+    }
+    let mut total_size = 0;
+    for file in &partial.files {
+        total_size += file.metadata()?.size();
+        println!("{}", file.display());
+    }
+    for dir in &partial.dirs {
+        println!("{}/", dir.display());
+    }
+    let total_size = ByteSize::b(total_size).to_string();
+    let prompt = "==>".if_supports_color(Stdout, |t| t.green());
+    println!(
+        "{}",
+        format!(
+            "{prompt} {} partial downloads using {} of disk",
+            partial.files.len(),
+            total_size.if_supports_color(Stdout, |t| t.bright_blue())
+        )
+        .if_supports_color(Stdout, |t| t.bold())
+    );
+    let mut cli = Cli::new();
+    let answer = cli.confirm("Remove?", Answer::Yes)?;
+    if !answer.as_bool() {
+        return Ok(());
+    }
+    let mut removed_size = 0;
+    for file in &partial.files {
+        removed_size += remove_file(file)?;
+    }
+    for dir in &partial.dirs {
+        log::info!("Removing directory: {}", dir.display());
+        fs::remove_dir(dir)?;
+    }
+    let files_count = partial.files.len() + partial.dirs.len();
+    print_removed_stats(partial.files.len(), files_count, removed_size);
+    Ok(())
+}
+
+pub fn cache_entry_regex() -> anyhow::Result<Regex> {
+    Regex::new(CACHE_ENTRY_REGEX)
+        .context("failed to compile regular expression for cache file names")
+}
+
+pub fn parse_file_name(file_name: &str, regex: &Regex, dir: &Path) -> Option<CacheEntry> {
     let captures = regex.captures(file_name)?;
-    Some(CacheEntry {
+    let entry = CacheEntry {
         pkg_name: captures.name("name")?.as_str().to_string(),
         version: captures.name("version")?.as_str().to_string(),
         subversion: captures.name("subversion")?.as_str().to_string(),
         arch: captures.name("arch")?.as_str().to_string(),
         ext: captures.name("ext")?.as_str().to_string(),
-    })
+        dir: dir.to_path_buf(),
+    };
+    // `path()` is built from the parsed parts, so it has to point to this very file
+    (entry.to_string() == file_name).then_some(entry)
+}
+
+impl CleanPolicy {
+    /// No rules are given, so the entire cache is removed including installed versions
+    pub const fn is_clean_all(&self) -> bool {
+        self.keep == 0 && self.older_than.is_none() && self.max_size.is_none()
+    }
 }
 
 impl CacheEntry {
-    fn path(&self) -> PathBuf {
-        Path::new(PACMAN_CACHE_PATH).join(self.to_string())
+    pub fn path(&self) -> PathBuf {
+        self.dir.join(self.to_string())
+    }
+
+    /// Package archive, e.g. `pkg.tar.zst`, but not its signature or partial download
+    pub fn is_pkg_archive(&self) -> bool {
+        self.ext == "pkg.tar"
+            || self
+                .ext
+                .strip_prefix("pkg.tar.")
+                .is_some_and(|compression| !compression.contains('.') && compression != "sig")
+    }
+
+    fn is_signature(&self) -> bool {
+        Path::new(&self.ext)
+            .extension()
+            .is_some_and(|ext| ext == "sig")
+    }
+
+    /// Comparison of the archive version with the installed version of the package
+    pub fn installed_status(&self, alpm: &PacrsAlpm) -> Option<InstalledStatus> {
+        let pkg = alpm.localdb().pkg(self.pkg_name.as_str()).ok()?;
+        let status = match alpm::vercmp(self.full_version().as_str(), pkg.version().as_str()) {
+            Ordering::Less => InstalledStatus::Older,
+            Ordering::Equal => InstalledStatus::Installed,
+            Ordering::Greater => InstalledStatus::Newer,
+        };
+        Some(status)
+    }
+
+    /// Version without pkgrel
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// Version in pacman format: `[epoch:]pkgver-pkgrel`
+    pub fn full_version(&self) -> String {
+        format!("{}-{}", self.version, self.subversion)
+    }
+
+    pub fn sig_path(&self) -> PathBuf {
+        self.dir.join(format!("{self}.sig"))
+    }
+
+    /// Size of the archive and its signature if it exists
+    fn size_with_sig(&self) -> anyhow::Result<u64> {
+        let sig_path = self.sig_path();
+        let sig_size = if sig_path.exists() {
+            sig_path.metadata()?.size()
+        } else {
+            0
+        };
+        Ok(self.path().metadata()?.size() + sig_size)
     }
 }
 
@@ -161,18 +576,311 @@ impl fmt::Display for CacheEntry {
 }
 
 impl PartialOrd for CacheEntry {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
     }
 }
 
 impl Ord for CacheEntry {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+    fn cmp(&self, other: &Self) -> Ordering {
         self.pkg_name
             .cmp(&other.pkg_name)
-            .then(self.version.cmp(&other.version))
-            .then(self.subversion.cmp(&other.subversion))
+            .then_with(|| alpm::vercmp(self.full_version(), other.full_version()))
             .then(self.arch.cmp(&other.arch))
             .then(self.ext.cmp(&other.ext))
+            // Versions like "1.0" and "1.00" are equal for vercmp
+            .then_with(|| self.version.cmp(&other.version))
+            .then_with(|| self.subversion.cmp(&other.subversion))
+            .then_with(|| self.dir.cmp(&other.dir))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alpm::fixture::{self, FixturePkg};
+    use crate::args::{parse_age, parse_size};
+    use crate::utils::test_dir;
+
+    fn entry(file_name: &str) -> CacheEntry {
+        entry_in("/var/cache/pacman/pkg", file_name)
+    }
+
+    fn entry_in(dir: &str, file_name: &str) -> CacheEntry {
+        let regex = cache_entry_regex().unwrap();
+        parse_file_name(file_name, &regex, Path::new(dir)).unwrap()
+    }
+
+    fn names(entries: &[CacheEntry]) -> Vec<String> {
+        let mut names: Vec<String> = entries.iter().map(ToString::to_string).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn version_parts_are_compared_numerically() {
+        assert!(entry("foo-1.10-1-x86_64.pkg.tar.zst") > entry("foo-1.9-1-x86_64.pkg.tar.zst"));
+        assert!(entry("foo-1.0.1-1-x86_64.pkg.tar.zst") > entry("foo-1.0-1-x86_64.pkg.tar.zst"));
+    }
+
+    #[test]
+    fn epoch_takes_precedence() {
+        assert!(entry("foo-1:1.0-1-x86_64.pkg.tar.zst") > entry("foo-2.0-1-x86_64.pkg.tar.zst"));
+        assert!(entry("foo-2:1.0-1-x86_64.pkg.tar.zst") > entry("foo-1:3.0-1-x86_64.pkg.tar.zst"));
+    }
+
+    #[test]
+    fn pkgrel_is_compared() {
+        let first = entry("foo-1.0-1-x86_64.pkg.tar.zst");
+        let rebuild = entry("foo-1.0-1.1-x86_64.pkg.tar.zst");
+        let second = entry("foo-1.0-2-x86_64.pkg.tar.zst");
+        assert!(second > first);
+        assert!(second > rebuild);
+        assert!(rebuild > first);
+    }
+
+    #[test]
+    fn pre_releases_are_older_than_release() {
+        assert!(entry("foo-1.0rc1-1-x86_64.pkg.tar.zst") < entry("foo-1.0-1-x86_64.pkg.tar.zst"));
+        assert!(
+            entry("foo-1.0alpha-1-x86_64.pkg.tar.zst") < entry("foo-1.0beta-1-x86_64.pkg.tar.zst")
+        );
+        assert!(
+            entry("foo-1.0rc2-1-x86_64.pkg.tar.zst") > entry("foo-1.0rc1-1-x86_64.pkg.tar.zst")
+        );
+    }
+
+    #[test]
+    fn equal_versions_with_different_notation_are_ordered() {
+        let short = entry("foo-1.0-1-x86_64.pkg.tar.zst");
+        let long = entry("foo-1.00-1-x86_64.pkg.tar.zst");
+        assert_ne!(short.cmp(&long), Ordering::Equal);
+    }
+
+    #[test]
+    fn keep_newest_keeps_given_number_of_each_package() {
+        let cache = vec![
+            entry("foo-1.9-1-x86_64.pkg.tar.zst"),
+            entry("bar-2.0-1-any.pkg.tar.zst"),
+            entry("foo-1.10-1-x86_64.pkg.tar.zst"),
+            entry("bar-1:1.0-1-any.pkg.tar.zst"),
+            entry("foo-1.8-1-x86_64.pkg.tar.zst"),
+            entry("baz-0.1-1-i686.pkg.tar.zst"),
+            entry("bar-3.0-1-any.pkg.tar.zst"),
+        ];
+        assert_eq!(
+            names(&keep_newest(cache.clone(), 1)),
+            [
+                "bar-2.0-1-any.pkg.tar.zst",
+                "bar-3.0-1-any.pkg.tar.zst",
+                "foo-1.8-1-x86_64.pkg.tar.zst",
+                "foo-1.9-1-x86_64.pkg.tar.zst",
+            ]
+        );
+        assert_eq!(
+            names(&keep_newest(cache.clone(), 2)),
+            ["bar-2.0-1-any.pkg.tar.zst", "foo-1.8-1-x86_64.pkg.tar.zst"]
+        );
+        assert!(keep_newest(cache.clone(), 3).is_empty());
+        assert_eq!(keep_newest(cache.clone(), 0).len(), cache.len());
+    }
+
+    #[test]
+    fn keep_newest_keeps_versions_in_each_directory() {
+        let cache = vec![
+            entry_in("/var/cache/pacman/pkg", "foo-2.0-1-x86_64.pkg.tar.zst"),
+            entry_in("/var/cache/pacman/pkg", "foo-1.0-1-x86_64.pkg.tar.zst"),
+            entry_in("/srv/repo", "foo-3.0-1-x86_64.pkg.tar.zst"),
+            entry_in("/srv/repo", "foo-0.9-1-x86_64.pkg.tar.zst"),
+        ];
+        let candidates: Vec<PathBuf> = keep_newest(cache, 1).iter().map(CacheEntry::path).collect();
+        assert_eq!(
+            candidates,
+            [
+                PathBuf::from("/srv/repo/foo-0.9-1-x86_64.pkg.tar.zst"),
+                PathBuf::from("/var/cache/pacman/pkg/foo-1.0-1-x86_64.pkg.tar.zst"),
+            ]
+        );
+    }
+
+    #[test]
+    fn pkg_archives_are_recognized() {
+        assert!(entry("foo-1.0-1-x86_64.pkg.tar.zst").is_pkg_archive());
+        assert!(entry("foo-1.0-1-x86_64.pkg.tar.xz").is_pkg_archive());
+        assert!(entry("foo-1.0-1-any.pkg.tar").is_pkg_archive());
+        assert!(!entry("foo-1.0-1-x86_64.pkg.tar.zst.sig").is_pkg_archive());
+        assert!(!entry("foo-1.0-1-x86_64.pkg.tar.zst.part").is_pkg_archive());
+        assert!(entry("foo-1.0-1-x86_64.pkg.tar.zst.sig").is_signature());
+    }
+
+    #[test]
+    fn junk_names_are_not_parsed() {
+        let regex = cache_entry_regex().unwrap();
+        let dir = Path::new("/var/cache/pacman/pkg");
+        for name in [
+            "foo-1.0-1-x86_64.pkg.tar.zst~",
+            "old foo-1.0-1-x86_64.pkg.tar.zst",
+            "foo.pkg.tar.zst",
+            "notes.txt",
+        ] {
+            assert!(parse_file_name(name, &regex, dir).is_none(), "{name}");
+        }
+    }
+
+    #[test]
+    fn scan_dir_skips_everything_except_archives() {
+        let dir = test_dir("scan");
+        for file in [
+            "foo-1.0-1-x86_64.pkg.tar.zst",
+            "foo-1.0-1-x86_64.pkg.tar.zst.sig",
+            "bar-2.0-1-any.pkg.tar.zst.part",
+            "foo-1.0-1-x86_64.pkg.tar.zst~",
+            "notes.txt",
+        ] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        fs::create_dir(dir.join("download-abc")).unwrap();
+
+        let cache = scan_dir(&dir, &cache_entry_regex().unwrap()).unwrap();
+        let archives: Vec<PathBuf> = cache.archives.iter().map(CacheEntry::path).collect();
+        assert_eq!(archives, [dir.join("foo-1.0-1-x86_64.pkg.tar.zst")]);
+        let mut unknown = cache.unknown;
+        unknown.sort();
+        assert_eq!(
+            unknown,
+            [
+                dir.join("bar-2.0-1-any.pkg.tar.zst.part"),
+                dir.join("download-abc"),
+                dir.join("foo-1.0-1-x86_64.pkg.tar.zst~"),
+                dir.join("notes.txt"),
+            ]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Write an archive of `size` bytes modified `age` ago, with a signature of `sig_size` bytes
+    fn write_archive(
+        dir: &Path,
+        file_name: &str,
+        size: usize,
+        sig_size: Option<usize>,
+        age: Duration,
+    ) -> CacheEntry {
+        let path = dir.join(file_name);
+        fs::write(&path, vec![0; size]).unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() - age)
+            .unwrap();
+        if let Some(sig_size) = sig_size {
+            fs::write(dir.join(format!("{file_name}.sig")), vec![0; sig_size]).unwrap();
+        }
+        let regex = cache_entry_regex().unwrap();
+        parse_file_name(file_name, &regex, dir).unwrap()
+    }
+
+    const DAY: Duration = Duration::from_hours(24);
+
+    #[test]
+    fn size_includes_signature() {
+        let dir = test_dir("size");
+        let signed = write_archive(&dir, "foo-1.0-1-any.pkg.tar.zst", 100, Some(10), DAY);
+        let unsigned = write_archive(&dir, "bar-1.0-1-any.pkg.tar.zst", 100, None, DAY);
+        assert_eq!(signed.size_with_sig().unwrap(), 110);
+        assert_eq!(unsigned.size_with_sig().unwrap(), 100);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn older_than_keeps_only_old_archives() {
+        let dir = test_dir("older-than");
+        let cache = vec![
+            write_archive(&dir, "foo-1.0-1-any.pkg.tar.zst", 1, None, 10 * DAY),
+            write_archive(&dir, "foo-2.0-1-any.pkg.tar.zst", 1, None, 2 * DAY),
+            write_archive(&dir, "bar-1.0-1-any.pkg.tar.zst", 1, None, Duration::ZERO),
+        ];
+        let old = older_than(cache, 7 * DAY, SystemTime::now()).unwrap();
+        assert_eq!(names(&old), ["foo-1.0-1-any.pkg.tar.zst"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn fit_in_size_removes_oldest_first() {
+        let dir = test_dir("fit-in-size");
+        let cache = vec![
+            write_archive(&dir, "foo-1.0-1-any.pkg.tar.zst", 100, Some(10), 3 * DAY),
+            write_archive(&dir, "foo-2.0-1-any.pkg.tar.zst", 100, None, 2 * DAY),
+            write_archive(&dir, "bar-1.0-1-any.pkg.tar.zst", 100, None, DAY),
+        ];
+        // Removing the oldest archive with its signature isn't enough to fit in 199 bytes
+        let fitted = fit_in_size(cache.clone(), 310, 199).unwrap();
+        assert_eq!(
+            names(&fitted),
+            ["foo-1.0-1-any.pkg.tar.zst", "foo-2.0-1-any.pkg.tar.zst"]
+        );
+        assert!(fit_in_size(cache.clone(), 310, 310).unwrap().is_empty());
+        // Cache can't fit even if all candidates are removed
+        assert_eq!(fit_in_size(cache, 1000, 100).unwrap().len(), 3);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn installed_versions_are_protected() {
+        let dir = test_dir("protect-installed");
+        let alpm = fixture::alpm(&dir, &[FixturePkg::new("foo")]);
+        let mut candidates = vec![
+            entry("foo-1.0-1-x86_64.pkg.tar.zst"),
+            entry("foo-0.9-1-x86_64.pkg.tar.zst"),
+            entry("bar-1.0-1-x86_64.pkg.tar.zst"),
+        ];
+        protect_installed(&alpm, &mut candidates);
+        assert_eq!(
+            names(&candidates),
+            [
+                "bar-1.0-1-x86_64.pkg.tar.zst",
+                "foo-0.9-1-x86_64.pkg.tar.zst"
+            ]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn ages_are_parsed() {
+        assert_eq!(parse_age("30"), Ok(30 * DAY));
+        assert_eq!(parse_age("12h"), Ok(Duration::from_hours(12)));
+        assert_eq!(parse_age("2w"), Ok(14 * DAY));
+        assert!(parse_age("3m").unwrap_err().contains("unknown unit 'm'"));
+        assert!(
+            parse_age("d")
+                .unwrap_err()
+                .contains("doesn't start with a number")
+        );
+        assert!(
+            parse_age("99999999999999999w")
+                .unwrap_err()
+                .contains("too big")
+        );
+    }
+
+    #[test]
+    fn sizes_are_parsed() {
+        assert_eq!(parse_size("1024"), Ok(1024));
+        assert_eq!(parse_size("1K"), Ok(1024));
+        assert_eq!(parse_size("500MiB"), Ok(500 << 20));
+        assert_eq!(parse_size("5gb"), Ok(5 << 30));
+        assert_eq!(parse_size("2T"), Ok(2 << 40));
+        assert!(parse_size("1P").unwrap_err().contains("unknown unit 'P'"));
+        assert!(
+            parse_size("G")
+                .unwrap_err()
+                .contains("doesn't start with a number")
+        );
+        assert!(
+            parse_size("99999999999999999T")
+                .unwrap_err()
+                .contains("too big")
+        );
     }
 }
@@ -37,6 +37,28 @@ impl Cli {
         })
     }
 
+    /// Ask user to choose one of "count" numbered items. Returns index of chosen item or `None` if
+    /// the answer is empty
+    pub fn select(
+        &mut self,
+        question: impl fmt::Display,
+        count: usize,
+    ) -> io::Result<Option<usize>> {
+        loop {
+            print!("{question} [1-{count}]: ");
+            self.stdout.flush()?;
+            let buf = self.read_single_line()?;
+            let answer = buf.trim();
+            if answer.is_empty() {
+                return Ok(None);
+            }
+            match answer.parse::<usize>() {
+                Ok(number) if (1..=count).contains(&number) => return Ok(Some(number - 1)),
+                _ => println!("Enter a number from 1 to {count}"),
+            }
+        }
+    }
+
     pub fn read_single_line(&self) -> io::Result<String> {
         let mut buf = String::new();
         self.stdin.read_line(&mut buf)?;
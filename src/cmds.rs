@@ -1,12 +1,97 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use anyhow::Context;
+use etcetera::BaseStrategy;
+
 use crate::Cmd;
+use crate::args::AurHelper;
 use crate::utils::{is_root, which};
 
 pub const PACMAN_BIN: &str = "pacman";
-pub const PARU_BIN: &str = "paru";
 const SUDO_BIN: &str = "sudo";
 
+/// AUR helpers in order of preference for auto detection
+const AUR_HELPERS: [AurHelper; 3] = [AurHelper::Paru, AurHelper::Yay, AurHelper::Pikaur];
+
+static AUR_HELPER: OnceLock<AurHelper> = OnceLock::new();
+
+/// Choose AUR helper for the whole program run. If it isn't chosen the first installed one is used
+pub fn set_aur_helper(helper: Option<AurHelper>) {
+    AUR_HELPER.get_or_init(|| helper.unwrap_or_else(detect_aur_helper));
+}
+
+pub fn aur_helper() -> AurHelper {
+    *AUR_HELPER.get_or_init(detect_aur_helper)
+}
+
+fn detect_aur_helper() -> AurHelper {
+    AUR_HELPERS
+        .into_iter()
+        .find(|helper| helper.bin().is_some_and(|bin| which::which(bin).is_ok()))
+        .unwrap_or(AurHelper::Pacman)
+}
+
+/// All supported AUR helpers accept pacman syntax for sync, query, search and info operations,
+/// so only their differences are described here
+impl AurHelper {
+    pub const fn bin(self) -> Option<&'static str> {
+        match self {
+            Self::Paru => Some("paru"),
+            Self::Yay => Some("yay"),
+            Self::Pikaur => Some("pikaur"),
+            Self::Pacman => None,
+        }
+    }
+
+    /// Arguments for removing unneeded dependencies if the helper supports it
+    pub const fn clean_deps_args(self) -> Option<&'static [&'static str]> {
+        match self {
+            Self::Paru => Some(&["-c"]),
+            Self::Yay => Some(&["-Yc"]),
+            Self::Pikaur | Self::Pacman => None,
+        }
+    }
+
+    /// Directory where the helper keeps its cache, `$XDG_CACHE_HOME/<helper>`
+    pub fn cache_dir(self) -> anyhow::Result<Option<PathBuf>> {
+        let Some(bin) = self.bin() else {
+            return Ok(None);
+        };
+        Ok(Some(base_dirs(bin)?.cache_dir().join(bin)))
+    }
+
+    /// Directory with clones of AUR packages
+    pub fn clone_dir(self) -> anyhow::Result<Option<PathBuf>> {
+        let Some(bin) = self.bin() else {
+            return Ok(None);
+        };
+        let dirs = base_dirs(bin)?;
+        let clone_dir = match self {
+            Self::Paru => dirs.cache_dir().join(bin).join("clone"),
+            // pikaur keeps clones as data to show diffs of PKGBUILDs on updates
+            Self::Pikaur => dirs.data_dir().join(bin).join("aur_repos"),
+            Self::Yay | Self::Pacman => dirs.cache_dir().join(bin),
+        };
+        Ok(Some(clone_dir))
+    }
+
+    /// Directory with built packages if the helper doesn't keep them in the clones
+    pub fn pkg_dir(self) -> anyhow::Result<Option<PathBuf>> {
+        match self {
+            Self::Pikaur => Ok(self.cache_dir()?.map(|cache_dir| cache_dir.join("pkg"))),
+            Self::Paru | Self::Yay | Self::Pacman => Ok(None),
+        }
+    }
+}
+
+/// XDG base directories of the user
+fn base_dirs(bin: &str) -> anyhow::Result<impl BaseStrategy> {
+    etcetera::choose_base_strategy().with_context(|| format!("failed to find {bin} directories"))
+}
+
 pub fn pacman() -> Cmd {
     Cmd::new(PACMAN_BIN)
 }
@@ -19,20 +104,20 @@ pub fn sudo_pacman() -> Cmd {
     sudo().arg(PACMAN_BIN)
 }
 
-pub fn paru_if_present() -> Option<Cmd> {
-    which(PARU_BIN)
+pub fn aur_helper_if_present() -> Option<Cmd> {
+    aur_helper().bin().and_then(which)
 }
 
-pub fn paru_or_sudo_pacman() -> Cmd {
-    paru_if_present().unwrap_or_else(|| {
-        log::info!("paru not founded. Using pacman.");
+pub fn aur_helper_or_sudo_pacman() -> Cmd {
+    aur_helper_if_present().unwrap_or_else(|| {
+        log::info!("AUR helper not founded. Using pacman.");
         sudo_pacman()
     })
 }
 
-pub fn paru_or_pacman() -> Cmd {
-    paru_if_present().unwrap_or_else(|| {
-        log::info!("paru not founded. Using pacman.");
+pub fn aur_helper_or_pacman() -> Cmd {
+    aur_helper_if_present().unwrap_or_else(|| {
+        log::info!("AUR helper not founded. Using pacman.");
         pacman()
     })
 }
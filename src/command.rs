@@ -1,20 +1,44 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
+use std::collections::VecDeque;
 use std::ffi::OsStr;
-use std::io;
-use std::process::{Command, ExitStatus, Stdio};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::process::{ChildStderr, Command, ExitStatus, Stdio};
 use std::str::{self, Utf8Error};
+use std::thread;
 
 use derive_more::Display;
 
+/// Number of last stderr lines which are kept for error reports
+const STDERR_TAIL_LINES: usize = 10;
+/// Exit codes of failed commands are shifted by this value, so they don't overlap with exit codes
+/// of pacrs itself
+const PASSED_EXIT_CODE_BASE: u8 = 100;
+
 pub struct Cmd {
     cmd: Command,
+    stderr: StderrMode,
+}
+
+/// What is done with stderr of the command
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum StderrMode {
+    /// Stderr is inherited, but it's grabbed together with the output
+    Default,
+    /// Stderr is shown to the user and its tail is kept for error reports
+    Capture,
+    /// Stderr is shown to the user even if the output is grabbed
+    Pipe,
 }
 
 impl Cmd {
     pub fn new(bin: &str) -> Self {
         let cmd = Command::new(bin);
-        Self { cmd }
+        Self {
+            cmd,
+            stderr: StderrMode::Default,
+        }
     }
 
     pub fn arg<S>(mut self, arg: S) -> Self
@@ -34,22 +58,49 @@ impl Cmd {
         self
     }
 
-    pub fn hide_output(mut self) -> Self {
-        self.cmd.stderr(Stdio::null()).stdout(Stdio::null());
+    pub fn pipe_stderr(mut self) -> Self {
+        self.cmd.stderr(std::io::stderr());
+        self.stderr = StderrMode::Pipe;
         self
     }
 
-    pub fn pipe_stderr(mut self) -> Self {
-        self.cmd.stderr(std::io::stderr());
+    /// Keep the tail of stderr for recognizing the failure. Only for non-interactive commands:
+    /// stderr of the command is not a terminal anymore, so its colors and progress bars change
+    pub const fn capture_stderr(mut self) -> Self {
+        self.stderr = StderrMode::Capture;
         self
     }
 
     pub fn execute(mut self) -> Result<ExitStatus> {
-        fn spawn_and_wait(cmd: &mut Command) -> std::result::Result<ExitStatus, io::Error> {
-            cmd.spawn()?.wait()
+        if self.stderr == StderrMode::Capture {
+            self.cmd.stderr(Stdio::piped());
         }
+        let mut child = self
+            .cmd
+            .spawn()
+            .map_err(|source| Error::execute(&self.cmd, source))?;
+        let tail = child
+            .stderr
+            .take()
+            .map(|stderr| thread::spawn(move || forward_stderr(stderr)));
+        let exit_status = child
+            .wait()
+            .map_err(|source| Error::execute(&self.cmd, source))?;
+        let stderr_tail = tail.and_then(|tail| tail.join().ok());
+        if !exit_status.success() {
+            return Err(Error::failed(&self.cmd, exit_status, stderr_tail, true));
+        }
+        Ok(exit_status)
+    }
 
-        spawn_and_wait(&mut self.cmd).map_err(|source| Error::execute(&self.cmd, source))
+    /// Execute command which ends with non-zero code if nothing is found, e.g. `pacman -Qs`.
+    /// Returns `false` in such case
+    pub fn execute_search(self) -> Result<bool> {
+        match self.capture_stderr().execute() {
+            Ok(_) => Ok(true),
+            Err(err) if err.is_nothing_found() => Ok(false),
+            Err(err) => Err(err),
+        }
     }
 
     pub fn execute_and_grub_output(mut self) -> Result<String> {
@@ -59,7 +110,21 @@ impl Cmd {
             .map_err(|source| Error::execute(&self.cmd, source))?;
 
         if !output.status.success() {
-            return Err(Error::ended_with_non_zero(&self.cmd, output.status));
+            // Piped stderr goes to the terminal and isn't captured
+            let stderr_tail = (self.stderr != StderrMode::Pipe).then(|| {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                let lines: Vec<&str> = stderr.lines().collect();
+                lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..]
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect()
+            });
+            return Err(Error::failed(
+                &self.cmd,
+                output.status,
+                stderr_tail,
+                self.stderr == StderrMode::Pipe,
+            ));
         }
 
         let string =
@@ -77,11 +142,54 @@ impl Cmd {
     }
 }
 
+/// Print stderr of the child process as soon as it comes and return its last lines. Output isn't
+/// buffered by lines, so prompts without trailing newline are shown
+fn forward_stderr(mut stderr: ChildStderr) -> Vec<String> {
+    let mut tail = VecDeque::with_capacity(STDERR_TAIL_LINES);
+    let mut our_stderr = io::stderr();
+    let mut buf = [0; 4096];
+    let mut line = Vec::new();
+    loop {
+        let read = match stderr.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        };
+        let _ = our_stderr.write_all(&buf[..read]);
+        let _ = our_stderr.flush();
+        for &byte in &buf[..read] {
+            if byte == b'\n' {
+                push_line(&mut tail, &line);
+                line.clear();
+            } else {
+                line.push(byte);
+            }
+        }
+    }
+    if !line.is_empty() {
+        push_line(&mut tail, &line);
+    }
+    tail.into()
+}
+
+fn push_line(tail: &mut VecDeque<String>, line: &[u8]) {
+    if tail.len() == STDERR_TAIL_LINES {
+        tail.pop_front();
+    }
+    tail.push_back(String::from_utf8_lossy(line).into_owned());
+}
+
 #[derive(Debug, thiserror::Error)]
-#[error("{command_name}: {kind}")]
+#[error("`{}`: {kind}", argv.join(" "))]
 pub struct Error {
-    pub command_name: String,
+    /// Program with all its arguments
+    pub argv: Vec<String>,
     pub kind: ErrorKind,
+    /// Last lines of stderr of the failed command, `None` if stderr wasn't captured
+    pub stderr_tail: Option<Vec<String>>,
+    /// Stderr was already shown to the user
+    pub stderr_shown: bool,
 }
 
 #[derive(Debug, Display)]
@@ -92,39 +200,140 @@ pub enum ErrorKind {
     Parse { source: Utf8Error },
     #[display("Command ended with error: {exit_status}")]
     EndedWithNonZero { exit_status: ExitStatus },
+    #[display("Database is locked")]
+    DbLocked { lock_file: Option<PathBuf> },
+    #[display("Conflicting files")]
+    ConflictingFiles,
+    #[display("Invalid signature")]
+    InvalidSignature,
+    #[display("Unresolvable dependencies")]
+    UnresolvableDeps,
+    #[display("Target not found")]
+    TargetNotFound,
 }
 
-trait CommandName {
-    fn name(&self) -> String;
-}
+impl Error {
+    fn execute(command: &Command, source: io::Error) -> Self {
+        Self::new(command, ErrorKind::Execute { source }, None, false)
+    }
 
-impl CommandName for Command {
-    fn name(&self) -> String {
-        self.get_program().to_string_lossy().to_string()
+    fn parse(command: &Command, source: Utf8Error) -> Self {
+        Self::new(command, ErrorKind::Parse { source }, None, false)
     }
-}
 
-impl Error {
-    fn execute(command: &Command, source: io::Error) -> Self {
+    fn failed(
+        command: &Command,
+        exit_status: ExitStatus,
+        stderr_tail: Option<Vec<String>>,
+        stderr_shown: bool,
+    ) -> Self {
+        let kind = ErrorKind::classify(stderr_tail.as_deref().unwrap_or_default(), exit_status);
+        Self::new(command, kind, stderr_tail, stderr_shown)
+    }
+
+    fn new(
+        command: &Command,
+        kind: ErrorKind,
+        stderr_tail: Option<Vec<String>>,
+        stderr_shown: bool,
+    ) -> Self {
+        let argv = std::iter::once(command.get_program())
+            .chain(command.get_args())
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect();
         Self {
-            command_name: command.name(),
-            kind: ErrorKind::Execute { source },
+            argv,
+            kind,
+            stderr_tail,
+            stderr_shown,
         }
     }
 
-    fn parse(command: &Command, source: Utf8Error) -> Self {
-        Self {
-            command_name: command.name(),
-            kind: ErrorKind::Parse { source },
+    /// Command ended with code 1 without any error message. Commands which don't capture stderr
+    /// are never considered as found nothing, since their error messages are unknown
+    pub fn is_nothing_found(&self) -> bool {
+        let ErrorKind::EndedWithNonZero { exit_status } = &self.kind else {
+            return false;
+        };
+        exit_status.code() == Some(1) && self.stderr_tail.as_ref().is_some_and(Vec::is_empty)
+    }
+}
+
+impl ErrorKind {
+    /// Recognize pacman failure by its error messages
+    fn classify(stderr: &[String], exit_status: ExitStatus) -> Self {
+        let contains = |pattern: &str| stderr.iter().any(|line| line.contains(pattern));
+        if contains("unable to lock database") || contains("could not lock database") {
+            let lock_file = stderr.iter().find_map(|line| {
+                let (_, path) = line.split_once("you can remove ")?;
+                Some(PathBuf::from(path.trim()))
+            });
+            return Self::DbLocked { lock_file };
+        }
+        if contains("conflicting files") || contains("exists in filesystem") {
+            return Self::ConflictingFiles;
+        }
+        if contains("invalid or corrupted package (PGP signature)")
+            || contains("signature from")
+            || contains("could not be looked up")
+        {
+            return Self::InvalidSignature;
         }
+        if contains("unable to satisfy dependency") || contains("could not satisfy dependencies") {
+            return Self::UnresolvableDeps;
+        }
+        if contains("target not found") {
+            return Self::TargetNotFound;
+        }
+        Self::EndedWithNonZero { exit_status }
     }
 
-    fn ended_with_non_zero(command: &Command, exit_status: ExitStatus) -> Self {
-        Self {
-            command_name: command.name(),
-            kind: ErrorKind::EndedWithNonZero { exit_status },
+    /// Exit code of pacrs if the command failed
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::Execute { .. } | Self::Parse { .. } => 1,
+            // Exit code of the command itself is passed shifted, e.g. 1 becomes 101
+            Self::EndedWithNonZero { exit_status } => exit_status
+                .code()
+                .and_then(|code| u8::try_from(code).ok())
+                .filter(|code| *code != 0)
+                .map_or(1, |code| code.saturating_add(PASSED_EXIT_CODE_BASE)),
+            Self::DbLocked { .. } => 10,
+            Self::ConflictingFiles => 11,
+            Self::InvalidSignature => 12,
+            Self::UnresolvableDeps => 13,
+            Self::TargetNotFound => 14,
         }
     }
+
+    /// Advice how to fix the failure
+    pub fn hint(&self) -> Option<String> {
+        let hint = match self {
+            Self::DbLocked { lock_file } => {
+                let lock_file = lock_file.as_ref().map_or_else(
+                    || String::from("the lock file"),
+                    |path| path.display().to_string(),
+                );
+                format!("remove {lock_file} if no pacman is running")
+            }
+            Self::ConflictingFiles => String::from(
+                "find owners of conflicting files with 'pacrs files --find FILE' and remove \
+                files which don't belong to any package",
+            ),
+            Self::InvalidSignature => String::from(
+                "update keyring with 'pacrs update archlinux-keyring' and check the cache for \
+                damaged archives with 'pacrs clean --verify'",
+            ),
+            Self::UnresolvableDeps => String::from(
+                "update the system with 'pacrs update' or install missing dependencies first",
+            ),
+            Self::TargetNotFound => String::from("look for the package with 'pacrs search'"),
+            Self::Execute { .. } | Self::Parse { .. } | Self::EndedWithNonZero { .. } => {
+                return None;
+            }
+        };
+        Some(hint)
+    }
 }
 
 type Result<T> = std::result::Result<T, Error>;
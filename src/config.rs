@@ -0,0 +1,226 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! pacrs configuration. System-wide config is read from `/etc/pacrs.toml`, user config from
+//! `$XDG_CONFIG_HOME/pacrs/config.toml`. User config values override system-wide ones and
+//! command line flags override both of them.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::Context;
+use clap::ValueEnum;
+use etcetera::BaseStrategy;
+use fs_err as fs;
+use serde::{Deserialize, Deserializer};
+use toml::Table;
+
+use crate::args::{AurHelper, PsSortBy};
+use crate::temp_db::DEFAULT_TTL;
+
+const SYSTEM_CONFIG_PATH: &str = "/etc/pacrs.toml";
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Config {
+    /// Colored output. By default colors are used if terminal supports them
+    pub color: Option<bool>,
+    /// AUR helper which is used if it's installed
+    #[serde(deserialize_with = "deserialize_value_enum")]
+    pub aur_helper: Option<AurHelper>,
+    pub clean: CleanConfig,
+    pub ps: PsConfig,
+    pub update: UpdateConfig,
+    pub sync: SyncConfig,
+    /// Keys which pacrs doesn't know, e.g. misspelled or from a newer version
+    #[serde(flatten)]
+    unknown: Table,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct CleanConfig {
+    /// How many versions of each package keep in the cache
+    pub keep: Option<u8>,
+    #[serde(flatten)]
+    unknown: Table,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct PsConfig {
+    #[serde(deserialize_with = "deserialize_value_enum")]
+    pub sort_by: Option<PsSortBy>,
+    #[serde(flatten)]
+    unknown: Table,
+}
+
+#[derive(Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct UpdateConfig {
+    /// Show reminder about finishing the update
+    pub reminder: bool,
+    #[serde(flatten)]
+    unknown: Table,
+}
+
+impl Default for UpdateConfig {
+    fn default() -> Self {
+        Self {
+            reminder: true,
+            unknown: Table::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct SyncConfig {
+    /// How many minutes the temp sync databases are used without updating
+    pub ttl: Option<u64>,
+    #[serde(flatten)]
+    unknown: Table,
+}
+
+impl SyncConfig {
+    pub fn ttl(&self) -> Duration {
+        self.ttl.map_or(DEFAULT_TTL, Duration::from_mins)
+    }
+}
+
+impl Config {
+    pub fn load() -> anyhow::Result<Self> {
+        let mut table = Table::new();
+        let user_config = user_config_path()?;
+        for path in [Path::new(SYSTEM_CONFIG_PATH), &user_config] {
+            if let Some(config) = read_table(path)? {
+                merge(&mut table, config);
+            }
+        }
+        table.try_into().context("failed to read configuration")
+    }
+
+    /// Keys of the config which are ignored, sections are prefixed to keys, e.g. "clean.kep"
+    pub fn unknown_keys(&self) -> Vec<String> {
+        let sections = [
+            ("", &self.unknown),
+            ("clean.", &self.clean.unknown),
+            ("ps.", &self.ps.unknown),
+            ("update.", &self.update.unknown),
+            ("sync.", &self.sync.unknown),
+        ];
+        sections
+            .into_iter()
+            .flat_map(|(section, unknown)| unknown.keys().map(move |key| format!("{section}{key}")))
+            .collect()
+    }
+}
+
+fn user_config_path() -> anyhow::Result<PathBuf> {
+    Ok(etcetera::choose_base_strategy()
+        .context("failed to find config dir")?
+        .config_dir()
+        .join("pacrs/config.toml"))
+}
+
+fn read_table(path: &Path) -> anyhow::Result<Option<Table>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(path)?;
+    let table = content
+        .parse()
+        .with_context(|| format!("failed to parse config '{}'", path.display()))?;
+    Ok(Some(table))
+}
+
+/// Merge tables recursively. Values from `other` take precedence
+fn merge(table: &mut Table, other: Table) {
+    for (key, value) in other {
+        match (table.get_mut(&key), value) {
+            (Some(toml::Value::Table(table)), toml::Value::Table(other)) => merge(table, other),
+            (_, value) => {
+                table.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Deserialize enums which are used as command line values by their names in command line
+fn deserialize_value_enum<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: ValueEnum,
+{
+    let Some(value) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    T::from_str(&value, true)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Config from system-wide and user config contents as `load` reads them
+    fn load(system: &str, user: &str) -> Config {
+        let mut table = Table::new();
+        for content in [system, user] {
+            merge(&mut table, content.parse().unwrap());
+        }
+        table.try_into().unwrap()
+    }
+
+    #[test]
+    fn user_config_overrides_system_config() {
+        let config = load(
+            "color = false\naur-helper = \"yay\"",
+            "color = true\n[update]\nreminder = false",
+        );
+        assert_eq!(config.color, Some(true));
+        assert_eq!(config.aur_helper, Some(AurHelper::Yay));
+        assert!(!config.update.reminder);
+    }
+
+    #[test]
+    fn sections_are_merged_by_keys() {
+        let config = load(
+            "[clean]\nkeep = 3\n[sync]\nttl = 30",
+            "[sync]\nttl = 5\n[ps]\nsort-by = \"user\"",
+        );
+        assert_eq!(config.clean.keep, Some(3));
+        assert_eq!(config.sync.ttl(), Duration::from_mins(5));
+        assert!(config.ps.sort_by.is_some());
+        assert!(config.update.reminder);
+    }
+
+    #[test]
+    fn defaults_are_used_without_config() {
+        let config = load("", "");
+        assert_eq!(config.color, None);
+        assert_eq!(config.clean.keep, None);
+        assert_eq!(config.sync.ttl(), DEFAULT_TTL);
+        assert!(config.update.reminder);
+        assert!(config.unknown_keys().is_empty());
+    }
+
+    #[test]
+    fn unknown_keys_are_reported_with_sections() {
+        let config = load(
+            "foo = 1\n[clean]\nkep = 3",
+            "[clean]\nkeep = 2\n[sync]\nttl = 5\nbar = true\n[extra]\nkey = 1",
+        );
+        assert_eq!(config.clean.keep, Some(2));
+        let mut keys = config.unknown_keys();
+        keys.sort();
+        assert_eq!(keys, ["clean.kep", "extra", "foo", "sync.bar"]);
+    }
+
+    #[test]
+    fn invalid_values_are_errors() {
+        let mut table = Table::new();
+        merge(&mut table, "aur-helper = \"unknown\"".parse().unwrap());
+        assert!(table.try_into::<Config>().is_err());
+    }
+}
@@ -0,0 +1,155 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Downgrade of packages to versions from the package cache and AUR helper clones
+
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, bail};
+use fs_err as fs;
+use jiff::Timestamp;
+use nix::unistd::getuid;
+use owo_colors::{OwoColorize, Stream::Stdout};
+
+use crate::alpm::{PacrsAlpm, architectures};
+use crate::clean::{self, CacheEntry};
+use crate::cli::Cli;
+use crate::cmds::{aur_helper, sudo_pacman};
+
+const IGNORED_FILE: &str = "pacrs/ignored";
+
+/// Install cached archive of the package with given or interactively chosen version
+pub fn downgrade(package: &str, version: Option<&str>, ignore: bool) -> anyhow::Result<()> {
+    // Fail before the downgrade if the package couldn't be ignored after it
+    let ignored_file = ignore.then(writable_ignored_file).transpose()?;
+    let alpm = PacrsAlpm::new()?;
+    let versions = cached_versions(package)?;
+    if versions.is_empty() {
+        bail!("no cached archives of package '{package}'");
+    }
+    let entry = if let Some(version) = version {
+        let found = versions
+            .iter()
+            .find(|entry| entry.full_version() == version || entry.version() == version);
+        let Some(entry) = found else {
+            let available: Vec<String> = versions.iter().map(CacheEntry::full_version).collect();
+            bail!(
+                "version '{version}' of package '{package}' is not cached. Available versions: {}",
+                available.join(", ")
+            );
+        };
+        entry
+    } else {
+        let Some(entry) = pick(&alpm, &versions)? else {
+            println!("Nothing selected");
+            return Ok(());
+        };
+        entry
+    };
+    sudo_pacman().arg("-U").arg(entry.path()).execute()?;
+    if let Some(ignored_file) = ignored_file {
+        add_ignored(&ignored_file, package)?;
+        println!("'{package}' will be ignored by 'pacrs update' until the end of the session");
+    }
+    Ok(())
+}
+
+/// Archives from the package cache and from the AUR helper clone of the package or its package
+/// directory
+fn cached_versions(package: &str) -> anyhow::Result<Vec<CacheEntry>> {
+    let archs = architectures()?;
+    let mut dirs = clean::cache_dirs(None)?;
+    let helper = aur_helper();
+    if let Some(clone_dir) = helper.clone_dir()? {
+        let package_clone = clone_dir.join(package);
+        if package_clone.is_dir() {
+            dirs.push(package_clone);
+        }
+    }
+    if let Some(pkg_dir) = helper.pkg_dir()?.filter(|dir| dir.is_dir()) {
+        dirs.push(pkg_dir);
+    }
+    let cache = clean::read_cache(&dirs)?;
+    Ok(clean::cached_versions(&cache, package, &archs))
+}
+
+fn pick<'a>(
+    alpm: &PacrsAlpm,
+    versions: &'a [CacheEntry],
+) -> anyhow::Result<Option<&'a CacheEntry>> {
+    for (number, entry) in versions.iter().enumerate() {
+        let modified = entry.path().metadata()?.modified()?;
+        let date = Timestamp::try_from(modified)?.strftime("%Y-%m-%d");
+        let status = entry
+            .installed_status(alpm)
+            .filter(|status| *status == clean::InstalledStatus::Installed)
+            .map(|status| format!(" [{status}]"))
+            .unwrap_or_default();
+        println!(
+            "{:>3}) {} {date}{}",
+            number + 1,
+            entry.full_version().if_supports_color(Stdout, |t| t.bold()),
+            status.if_supports_color(Stdout, |t| t.cyan())
+        );
+    }
+    let mut cli = Cli::new();
+    let index = cli.select("Version to install", versions.len())?;
+    Ok(index.map(|index| &versions[index]))
+}
+
+/// Packages ignored by update during the current session
+pub fn ignored_pkgs() -> anyhow::Result<Vec<String>> {
+    // Nothing could be ignored without the runtime directory
+    let Some(runtime_dir) = runtime_dir() else {
+        return Ok(Vec::new());
+    };
+    let path = runtime_dir.join(IGNORED_FILE);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = fs::read_to_string(path)?;
+    Ok(content.lines().map(str::to_owned).collect())
+}
+
+fn add_ignored(path: &Path, package: &str) -> anyhow::Result<()> {
+    let mut ignored = ignored_pkgs()?;
+    if ignored.iter().any(|ignored| ignored == package) {
+        return Ok(());
+    }
+    ignored.push(package.to_owned());
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, ignored.join("\n") + "\n")?;
+    Ok(())
+}
+
+/// Runtime directory of the user session, which is cleared when the session ends
+fn runtime_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+}
+
+/// File of ignored packages which the current user can write. Root doesn't write to the runtime
+/// directory of another user
+fn writable_ignored_file() -> anyhow::Result<PathBuf> {
+    let Some(runtime_dir) = runtime_dir() else {
+        bail!(
+            "'--ignore' needs the runtime directory of the session, but XDG_RUNTIME_DIR is not set"
+        );
+    };
+    let metadata = fs::metadata(&runtime_dir).with_context(|| {
+        format!(
+            "runtime directory of the session '{}' is not found",
+            runtime_dir.display()
+        )
+    })?;
+    if metadata.uid() != getuid().as_raw() {
+        bail!(
+            "'--ignore' can't be used with sudo. Run 'pacrs downgrade' as a regular user, it runs \
+            pacman with sudo itself"
+        );
+    }
+    Ok(runtime_dir.join(IGNORED_FILE))
+}
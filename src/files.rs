@@ -50,6 +50,6 @@ pub fn find_file(file: &str, update_index: bool, quiet: bool) -> anyhow::Result<
     if update_index {
         update_files_index(quiet)?;
     }
-    pacman().arg("-F").arg(file).execute()?;
+    pacman().arg("-F").arg(file).execute_search()?;
     Ok(())
 }
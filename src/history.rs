@@ -0,0 +1,512 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Transaction history from pacman log
+
+use std::path::Path;
+
+use anyhow::{Context, bail};
+use fs_err as fs;
+use jiff::civil::{Date, DateTime};
+use jiff::tz::TimeZone;
+use jiff::{Timestamp, ToSpan, Zoned};
+use owo_colors::{OwoColorize, Stream::Stdout};
+use serde::Serialize;
+
+use crate::alpm::pacmanconf;
+use crate::args::{HistoryAction, OutputFormat};
+use crate::output::{self, Record};
+
+pub struct Transaction {
+    pub started: Timestamp,
+    /// Command which started the transaction, e.g. "pacman -Syu"
+    pub command: Option<String>,
+    pub changes: Vec<Change>,
+    /// Transaction wasn't interrupted or failed
+    pub completed: bool,
+}
+
+pub struct Change {
+    pub time: Timestamp,
+    pub action: HistoryAction,
+    pub package: String,
+    /// Version before the transaction
+    pub old_version: Option<String>,
+    /// Version after the transaction
+    pub new_version: Option<String>,
+}
+
+pub struct HistoryFilter {
+    pub package: Option<String>,
+    pub actions: Vec<HistoryAction>,
+    /// Date or date with time, e.g. "2024-05-01" or "2024-05-01 10:00". "today" and "yesterday"
+    /// are also accepted
+    pub since: Option<String>,
+    /// Same as `since`. The whole day is included if only date is given
+    pub until: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ChangeRecord<'a> {
+    time: String,
+    /// Number of transaction in the log
+    transaction: usize,
+    command: Option<&'a str>,
+    action: &'static str,
+    name: &'a str,
+    old_version: Option<&'a str>,
+    new_version: Option<&'a str>,
+}
+
+impl Record for ChangeRecord<'_> {
+    const HEADERS: &'static [&'static str] = &[
+        "time",
+        "transaction",
+        "command",
+        "action",
+        "name",
+        "old_version",
+        "new_version",
+    ];
+}
+
+pub fn history(filter: &HistoryFilter, output: OutputFormat) -> anyhow::Result<()> {
+    let transactions = read()?;
+    let since = filter.since.as_deref().map(parse_since).transpose()?;
+    let until = filter.until.as_deref().map(parse_until).transpose()?;
+    let is_matched = |change: &Change| {
+        filter
+            .package
+            .as_ref()
+            .is_none_or(|package| &change.package == package)
+            && (filter.actions.is_empty() || filter.actions.contains(&change.action))
+            && since.is_none_or(|since| change.time >= since)
+            && until.is_none_or(|until| change.time < until)
+    };
+    let filtered = transactions
+        .iter()
+        .enumerate()
+        .filter_map(|(number, trans)| {
+            let changes: Vec<&Change> = trans.changes.iter().filter(|c| is_matched(c)).collect();
+            (!changes.is_empty()).then_some((number + 1, trans, changes))
+        });
+    if let Some(format) = output.machine() {
+        let mut records = Vec::new();
+        for (number, trans, changes) in filtered {
+            records.extend(changes.into_iter().map(|change| ChangeRecord {
+                time: change.time.to_string(),
+                transaction: number,
+                command: trans.command.as_deref(),
+                action: change.action.name(),
+                name: &change.package,
+                old_version: change.old_version.as_deref(),
+                new_version: change.new_version.as_deref(),
+            }));
+        }
+        return output::print_records(&records, format);
+    }
+    for (_, trans, changes) in filtered {
+        trans.print_header();
+        for change in changes {
+            change.print();
+        }
+    }
+    Ok(())
+}
+
+/// Read all transactions from pacman log
+pub fn read() -> anyhow::Result<Vec<Transaction>> {
+    let conf = pacmanconf()?;
+    let path = Path::new(&conf.log_file);
+    let content = fs::read_to_string(path)?;
+    Ok(parse(&content))
+}
+
+fn parse(content: &str) -> Vec<Transaction> {
+    let mut transactions = Vec::new();
+    let mut current: Option<Transaction> = None;
+    let mut last_command = None;
+    for line in content.lines() {
+        let Some((time, source, message)) = split_line(line) else {
+            continue;
+        };
+        if source == "PACMAN" {
+            if let Some(command) = message.strip_prefix("Running '") {
+                // Every pacman run is a new transaction. Old logs don't contain transaction
+                // boundaries, so it's the only place to end the previous one
+                transactions.extend(current.take());
+                last_command = Some(command.trim_end_matches('\'').to_owned());
+            }
+            continue;
+        }
+        if source != "ALPM" {
+            continue;
+        }
+        match message {
+            "transaction started" => {
+                transactions.extend(current.take());
+                current = Some(Transaction::new(time, last_command.take()));
+            }
+            "transaction completed" => {
+                if let Some(mut trans) = current.take() {
+                    trans.completed = true;
+                    transactions.push(trans);
+                }
+            }
+            "transaction failed" | "transaction interrupted" => {
+                transactions.extend(current.take());
+            }
+            _ => {
+                if let Some(change) = Change::parse(time, message) {
+                    // Old logs don't contain transaction boundaries
+                    current
+                        .get_or_insert_with(|| Transaction::new(time, last_command.take()))
+                        .changes
+                        .push(change);
+                }
+            }
+        }
+    }
+    transactions.extend(current);
+    transactions.retain(|trans| !trans.changes.is_empty());
+    transactions
+}
+
+/// Split log line like "[2024-05-01T10:00:00+0200] [ALPM] installed foo (1.0-1)"
+fn split_line(line: &str) -> Option<(Timestamp, &str, &str)> {
+    let (time, rest) = line.strip_prefix('[')?.split_once("] ")?;
+    let time = parse_log_time(time)?;
+    let (source, message) = rest.strip_prefix('[')?.split_once("] ")?;
+    Some((time, source, message))
+}
+
+fn parse_log_time(time: &str) -> Option<Timestamp> {
+    if let Ok(time) = Timestamp::strptime("%Y-%m-%dT%H:%M:%S%z", time) {
+        return Some(time);
+    }
+    // Format of old pacman versions in local time
+    let time = DateTime::strptime("%Y-%m-%d %H:%M", time).ok()?;
+    Some(time.to_zoned(TimeZone::system()).ok()?.timestamp())
+}
+
+fn parse_since(value: &str) -> anyhow::Result<Timestamp> {
+    match parse_date(value)? {
+        Moment::Day(date) => day_start(date),
+        Moment::Time(time) => Ok(time),
+    }
+}
+
+fn parse_until(value: &str) -> anyhow::Result<Timestamp> {
+    match parse_date(value)? {
+        Moment::Day(date) => day_start(date.checked_add(1.day())?),
+        Moment::Time(time) => Ok(time),
+    }
+}
+
+enum Moment {
+    Day(Date),
+    Time(Timestamp),
+}
+
+fn parse_date(value: &str) -> anyhow::Result<Moment> {
+    let today = Zoned::now().date();
+    match value {
+        "today" => return Ok(Moment::Day(today)),
+        "yesterday" => return Ok(Moment::Day(today.yesterday()?)),
+        _ => {}
+    }
+    if let Ok(date) = Date::strptime("%Y-%m-%d", value) {
+        return Ok(Moment::Day(date));
+    }
+    let Ok(time) = DateTime::strptime("%Y-%m-%d %H:%M", value) else {
+        bail!("invalid date '{value}', expected format is 'YYYY-MM-DD' or 'YYYY-MM-DD HH:MM'");
+    };
+    let time = time
+        .to_zoned(TimeZone::system())
+        .with_context(|| format!("failed to convert '{value}' to local time"))?;
+    Ok(Moment::Time(time.timestamp()))
+}
+
+fn day_start(date: Date) -> anyhow::Result<Timestamp> {
+    Ok(date.to_zoned(TimeZone::system())?.timestamp())
+}
+
+impl Transaction {
+    const fn new(started: Timestamp, command: Option<String>) -> Self {
+        Self {
+            started,
+            command,
+            changes: Vec::new(),
+            completed: false,
+        }
+    }
+
+    pub fn print_header(&self) {
+        let prompt = "==>".if_supports_color(Stdout, |t| t.green());
+        let mut header = format!("{prompt} {}", local_time(self.started));
+        if let Some(command) = &self.command {
+            header.push(' ');
+            header.push_str(command);
+        }
+        if !self.completed {
+            header.push_str(" (not completed)");
+        }
+        println!("{}", header.if_supports_color(Stdout, |t| t.bold()));
+    }
+}
+
+impl Change {
+    /// Parse message like "upgraded foo (1.0-1 -> 1.1-1)"
+    fn parse(time: Timestamp, message: &str) -> Option<Self> {
+        let (action, rest) = message.split_once(' ')?;
+        let action = match action {
+            "installed" => HistoryAction::Installed,
+            "upgraded" => HistoryAction::Upgraded,
+            "removed" => HistoryAction::Removed,
+            "downgraded" => HistoryAction::Downgraded,
+            "reinstalled" => HistoryAction::Reinstalled,
+            _ => return None,
+        };
+        let (package, versions) = rest.split_once(" (")?;
+        let versions = versions.strip_suffix(')')?;
+        let (old_version, new_version) = match versions.split_once(" -> ") {
+            Some((old, new)) => (Some(old), Some(new)),
+            None if action == HistoryAction::Removed => (Some(versions), None),
+            None if action == HistoryAction::Reinstalled => (Some(versions), Some(versions)),
+            None => (None, Some(versions)),
+        };
+        Some(Self {
+            time,
+            action,
+            package: package.to_owned(),
+            old_version: old_version.map(str::to_owned),
+            new_version: new_version.map(str::to_owned),
+        })
+    }
+
+    fn print(&self) {
+        let versions = match (&self.old_version, &self.new_version) {
+            (Some(old), Some(new)) if old != new => format!("{old} -> {new}"),
+            (Some(version), _) | (None, Some(version)) => version.clone(),
+            (None, None) => String::new(),
+        };
+        let action = format!("{:<11}", self.action.name());
+        let action = match self.action {
+            HistoryAction::Installed => action.if_supports_color(Stdout, |t| t.green()).to_string(),
+            HistoryAction::Removed => action.if_supports_color(Stdout, |t| t.red()).to_string(),
+            HistoryAction::Downgraded => {
+                action.if_supports_color(Stdout, |t| t.yellow()).to_string()
+            }
+            HistoryAction::Upgraded | HistoryAction::Reinstalled => {
+                action.if_supports_color(Stdout, |t| t.cyan()).to_string()
+            }
+        };
+        println!("    {action} {} {versions}", self.package);
+    }
+}
+
+impl HistoryAction {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Installed => "installed",
+            Self::Upgraded => "upgraded",
+            Self::Removed => "removed",
+            Self::Downgraded => "downgraded",
+            Self::Reinstalled => "reinstalled",
+        }
+    }
+}
+
+fn local_time(time: Timestamp) -> String {
+    time.to_zoned(TimeZone::system())
+        .strftime("%Y-%m-%d %H:%M")
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &str = "\
+[2024-05-01T10:00:00+0200] [PACMAN] Running 'pacman -Syu'
+[2024-05-01T10:00:01+0200] [PACMAN] synchronizing package lists
+[2024-05-01T10:00:05+0200] [ALPM] transaction started
+[2024-05-01T10:00:06+0200] [ALPM] upgraded linux (6.8.8.arch1-1 -> 6.8.9.arch1-1)
+[2024-05-01T10:00:06+0200] [ALPM-SCRIPTLET] ==> Building image from preset
+[2024-05-01T10:00:07+0200] [ALPM] installed python-rich (13.7.1-1)
+[2024-05-01T10:00:07+0200] [ALPM] warning: /etc/pacman.conf installed as /etc/pacman.conf.pacnew
+[2024-05-01T10:00:08+0200] [ALPM] transaction completed
+[2024-05-02T09:00:00+0200] [PACMAN] Running 'pacman -U /var/cache/pacman/pkg/vim-9.1.0-1-x86_64.pkg.tar.zst'
+[2024-05-02T09:00:01+0200] [ALPM] transaction started
+[2024-05-02T09:00:02+0200] [ALPM] downgraded vim (9.1.0300-1 -> 9.1.0-1)
+[2024-05-02T09:00:03+0200] [ALPM] transaction failed
+[2024-05-03T09:00:00+0200] [PACMAN] Running 'pacman -Rs foo'
+[2024-05-03T09:00:01+0200] [ALPM] transaction started
+[2024-05-03T09:00:01+0200] [ALPM] transaction completed
+[2024-05-04T09:00:00+0200] [PACMAN] Running 'pacman -S bash'
+[2024-05-04T09:00:01+0200] [ALPM] transaction started
+[2024-05-04T09:00:02+0200] [ALPM] reinstalled bash (5.2.026-2)
+[2024-05-04T09:00:03+0200] [ALPM] removed bash-completion (2.14.0-1)
+";
+
+    fn time(time: &str) -> Timestamp {
+        time.parse().unwrap()
+    }
+
+    fn change(message: &str) -> Option<Change> {
+        Change::parse(time("2024-05-01T08:00:00Z"), message)
+    }
+
+    fn versions(change: &Change) -> (Option<&str>, Option<&str>) {
+        (change.old_version.as_deref(), change.new_version.as_deref())
+    }
+
+    #[test]
+    fn log_time_iso() {
+        assert_eq!(
+            parse_log_time("2024-05-01T10:00:00+0200"),
+            Some(time("2024-05-01T08:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn log_time_old_format() {
+        let parsed = parse_log_time("2015-06-10 21:40").unwrap();
+        assert_eq!(local_time(parsed), "2015-06-10 21:40");
+    }
+
+    #[test]
+    fn log_time_invalid() {
+        assert_eq!(parse_log_time("2024-05-01T10:00:00"), None);
+        assert_eq!(parse_log_time("2024-05-01"), None);
+        assert_eq!(parse_log_time("yesterday"), None);
+    }
+
+    #[test]
+    fn change_actions() {
+        let installed = change("installed python-rich (13.7.1-1)").unwrap();
+        assert_eq!(installed.action.name(), "installed");
+        assert_eq!(installed.package, "python-rich");
+        assert_eq!(versions(&installed), (None, Some("13.7.1-1")));
+
+        let upgraded = change("upgraded linux (6.8.8.arch1-1 -> 6.8.9.arch1-1)").unwrap();
+        assert_eq!(upgraded.action.name(), "upgraded");
+        assert_eq!(
+            versions(&upgraded),
+            (Some("6.8.8.arch1-1"), Some("6.8.9.arch1-1"))
+        );
+
+        let downgraded = change("downgraded vim (9.1.0300-1 -> 9.1.0-1)").unwrap();
+        assert_eq!(downgraded.action.name(), "downgraded");
+        assert_eq!(versions(&downgraded), (Some("9.1.0300-1"), Some("9.1.0-1")));
+
+        let reinstalled = change("reinstalled bash (5.2.026-2)").unwrap();
+        assert_eq!(reinstalled.action.name(), "reinstalled");
+        assert_eq!(
+            versions(&reinstalled),
+            (Some("5.2.026-2"), Some("5.2.026-2"))
+        );
+
+        let removed = change("removed bash-completion (2.14.0-1)").unwrap();
+        assert_eq!(removed.action.name(), "removed");
+        assert_eq!(versions(&removed), (Some("2.14.0-1"), None));
+    }
+
+    #[test]
+    fn change_malformed() {
+        assert!(change("warning: /etc/pacman.conf installed as /etc/pacman.conf.pacnew").is_none());
+        assert!(change("running '20-systemd-sysusers.hook'...").is_none());
+        assert!(change("installed foo").is_none());
+        assert!(change("upgraded foo (1.0-1 -> 1.1-1").is_none());
+        assert!(change("installed").is_none());
+    }
+
+    #[test]
+    fn transactions() {
+        let transactions = parse(LOG);
+        // The completed transaction without changes is dropped
+        assert_eq!(transactions.len(), 3);
+
+        let upgrade = &transactions[0];
+        assert_eq!(upgrade.started, time("2024-05-01T08:00:05Z"));
+        assert_eq!(upgrade.command.as_deref(), Some("pacman -Syu"));
+        assert!(upgrade.completed);
+        let packages: Vec<&str> = upgrade.changes.iter().map(|c| c.package.as_str()).collect();
+        assert_eq!(packages, ["linux", "python-rich"]);
+        assert_eq!(upgrade.changes[1].time, time("2024-05-01T08:00:07Z"));
+
+        let failed = &transactions[1];
+        assert!(!failed.completed);
+        assert_eq!(failed.changes.len(), 1);
+        assert_eq!(failed.changes[0].action.name(), "downgraded");
+
+        // Log ends before the transaction is completed
+        let interrupted = &transactions[2];
+        assert!(!interrupted.completed);
+        assert_eq!(interrupted.command.as_deref(), Some("pacman -S bash"));
+        assert_eq!(interrupted.changes.len(), 2);
+    }
+
+    #[test]
+    fn transactions_old_log() {
+        let log = "\
+[2015-06-10 21:39] [PACMAN] Running 'pacman -S htop'
+[2015-06-10 21:40] [ALPM] installed htop (1.0.3-2)
+[2015-06-10 21:41] [ALPM] upgraded ncurses (5.9-7 -> 6.0-1)
+";
+        let transactions = parse(log);
+        assert_eq!(transactions.len(), 1);
+        let trans = &transactions[0];
+        assert_eq!(trans.command.as_deref(), Some("pacman -S htop"));
+        assert_eq!(local_time(trans.started), "2015-06-10 21:40");
+        assert_eq!(trans.changes.len(), 2);
+    }
+
+    #[test]
+    fn transactions_old_log_are_split_by_commands() {
+        let log = "\
+[2015-06-10 21:39] [PACMAN] Running 'pacman -S htop'
+[2015-06-10 21:40] [ALPM] installed htop (1.0.3-2)
+[2015-06-11 08:00] [PACMAN] Running 'pacman -Syu'
+[2015-06-11 08:01] [ALPM] upgraded ncurses (5.9-7 -> 6.0-1)
+[2015-06-11 08:01] [ALPM] upgraded readline (6.3.008-1 -> 6.3.008-2)
+[2015-06-12 10:00] [PACMAN] Running 'pacman -Q'
+[2015-06-12 10:05] [PACMAN] Running 'pacman -R htop'
+[2015-06-12 10:05] [ALPM] removed htop (1.0.3-2)
+";
+        let transactions = parse(log);
+        let commands: Vec<Option<&str>> = transactions
+            .iter()
+            .map(|trans| trans.command.as_deref())
+            .collect();
+        assert_eq!(
+            commands,
+            [
+                Some("pacman -S htop"),
+                Some("pacman -Syu"),
+                Some("pacman -R htop")
+            ]
+        );
+        let changes: Vec<usize> = transactions
+            .iter()
+            .map(|trans| trans.changes.len())
+            .collect();
+        assert_eq!(changes, [1, 2, 1]);
+    }
+
+    #[test]
+    fn malformed_lines() {
+        let log = "\
+garbage
+
+[2024-05-01T10:00:00+0200] installed foo (1.0-1)
+[yesterday] [ALPM] installed foo (1.0-1)
+[2024-05-01T10:00:00+0200] [ALPM installed foo (1.0-1)
+[2024-05-01T10:00:01+0200] [ALPM] installed bar (1.0-1)
+";
+        let transactions = parse(log);
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(transactions[0].changes.len(), 1);
+        assert_eq!(transactions[0].changes[0].package, "bar");
+        assert!(transactions[0].command.is_none());
+    }
+}
@@ -2,68 +2,241 @@
 
 mod alpm;
 mod args;
+mod aur_cache;
 mod clean;
 mod cli;
 mod cmds;
 mod command;
+mod config;
+mod downgrade;
 mod files;
+mod history;
+mod output;
 mod pacman;
 mod pacrs;
+mod preview;
 mod ps;
+mod rollback;
+mod srcinfo;
 mod temp_db;
+mod tree;
 mod utils;
+mod verify;
+mod why;
 
-use alpm::PacrsAlpm;
-use args::Args;
+use alpm::{PacrsAlpm, PkgFilter};
+use args::{Args, ColorWhen, Commands, OutputFormat};
+use clean::CleanPolicy;
 use command::Cmd;
+use config::Config;
 use files::{find_file, package_files};
+use history::HistoryFilter;
 use log::LevelFilter;
-use owo_colors::OwoColorize;
+use owo_colors::{OwoColorize, Stream::Stderr};
 use pacrs::package_search;
+use preview::{Operation, preview};
 use ps::ps;
+use temp_db::Refresh;
+use tree::TreeOptions;
+
+use std::path::PathBuf;
+use std::process::ExitCode;
 
 use anyhow::bail;
 use args::MarkGroup;
 use clap::Parser;
 
-fn main() -> anyhow::Result<()> {
+/// Exit code of `listupdates` if there are no updates. The same as `checkupdates` uses
+const NO_UPDATES_EXIT_CODE: u8 = 2;
+
+fn main() -> ExitCode {
     env_logger::builder().filter_level(LevelFilter::Off).build();
 
     let args = Args::parse();
-    match args {
-        Args::Packages {
+    match start(args) {
+        Ok(exit_code) => exit_code,
+        Err(err) => report_error(&err),
+    }
+}
+
+fn start(args: Args) -> anyhow::Result<ExitCode> {
+    let Args {
+        output,
+        helper,
+        color,
+        command,
+    } = args;
+    let config = Config::load()?;
+    let color = match color {
+        Some(ColorWhen::Always) => Some(true),
+        Some(ColorWhen::Never) => Some(false),
+        Some(ColorWhen::Auto) => None,
+        None => config.color,
+    };
+    if let Some(color) = color {
+        owo_colors::set_override(color);
+    }
+    for key in config.unknown_keys() {
+        eprintln!(
+            "{}: unknown config key '{key}' is ignored",
+            "Warning".if_supports_color(Stderr, |t| t.yellow())
+        );
+    }
+    cmds::set_aur_helper(helper.or(config.aur_helper));
+
+    run(command, output, &config)
+}
+
+/// Print the error with a hint if it's known how to fix it and choose exit code by its kind
+fn report_error(err: &anyhow::Error) -> ExitCode {
+    eprintln!("Error: {err:?}");
+    let Some(err) = err.downcast_ref::<command::Error>() else {
+        return ExitCode::FAILURE;
+    };
+    if !err.stderr_shown {
+        for line in err.stderr_tail.iter().flatten() {
+            eprintln!("    {line}");
+        }
+    }
+    if let Some(hint) = err.kind.hint() {
+        eprintln!("{}: {hint}", "Hint".if_supports_color(Stderr, |t| t.cyan()));
+    }
+    ExitCode::from(err.kind.exit_code())
+}
+
+#[allow(clippy::too_many_lines)] // Dispatch of all subcommands
+fn run(command: Commands, output: OutputFormat, config: &Config) -> anyhow::Result<ExitCode> {
+    match command {
+        Commands::Packages {
             search,
             orphaned,
             aur,
             explicit,
             deps,
-        } => packages(search, orphaned, aur, explicit, deps)?,
-        Args::Install { packages } => pacrs::install(packages)?,
-        Args::Remove {
+        } => {
+            let filter = PkgFilter {
+                orphaned,
+                foreign: aur,
+                explicit,
+                deps,
+            };
+            packages(search, filter, output)?;
+        }
+        Commands::Install {
+            packages,
+            from_cache,
+            dry_run,
+            refresh_group,
+        } => {
+            let refresh = Refresh::new(&refresh_group, config.sync.ttl());
+            install(packages, from_cache, dry_run, refresh)?;
+        }
+        Commands::Remove {
             packages,
             clean_deps,
-        } => pacrs::remove(&packages, clean_deps)?,
-        Args::Autoremove { packages } => autoremove(&packages)?,
-        Args::Update { packages, quiet } => update(&packages, quiet),
-        Args::Info {
+            dry_run,
+        } => remove(&packages, clean_deps, dry_run)?,
+        Commands::Autoremove { packages } => autoremove(&packages)?,
+        Commands::Update {
+            packages,
+            dry_run: true,
+            ..
+        } => preview(&Operation::Update {
+            packages: &packages,
+            refresh: Refresh::Expired(config.sync.ttl()),
+        })?,
+        Commands::Update {
+            packages, quiet, ..
+        } => update(&packages, quiet || !config.update.reminder)?,
+        Commands::Info {
             package,
             recursive_deps,
-        } => pacrs::info(&package, recursive_deps)?,
-        Args::Search { package } => pacrs::search(&package)?,
-        Args::Listupdates { verbose } => pacrs::list_updates(verbose)?,
-        Args::Files {
+            tree,
+            depth,
+            optional,
+            reverse,
+        } => {
+            if tree {
+                let options = TreeOptions {
+                    depth,
+                    optional,
+                    reverse,
+                };
+                pacrs::dependency_tree(&package, &options)?;
+            } else {
+                pacrs::info(&package, recursive_deps)?;
+            }
+        }
+        Commands::Search { package } => pacrs::search(&package)?,
+        Commands::Why { package } => pacrs::why(&package)?,
+        Commands::History {
+            package,
+            action,
+            since,
+            until,
+        } => {
+            let filter = HistoryFilter {
+                package,
+                actions: action,
+                since,
+                until,
+            };
+            history::history(&filter, output)?;
+        }
+        Commands::Rollback { transactions } => rollback::rollback(transactions)?,
+        Commands::Downgrade {
+            package,
+            version,
+            ignore,
+        } => downgrade::downgrade(&package, version.as_deref(), ignore)?,
+        Commands::Listupdates {
+            verbose,
+            refresh_group,
+        } => {
+            let refresh = Refresh::new(&refresh_group, config.sync.ttl());
+            let has_updates = pacrs::list_updates(verbose, refresh, output)?;
+            if !has_updates {
+                return Ok(ExitCode::from(NO_UPDATES_EXIT_CODE));
+            }
+        }
+        Commands::Files {
             package,
             find: file,
             not_update_index,
             quiet,
         } => files(package, file, not_update_index, quiet)?,
-        Args::Clean {
+        Commands::Clean {
             uninstalled,
             aur,
             keep,
+            older_than,
+            max_size,
             show_remove_candidates,
-        } => cache(uninstalled, aur, keep, show_remove_candidates)?,
-        Args::Mark {
+            cache_dir,
+            verify,
+            partial,
+        } => {
+            if verify {
+                pacrs::verify_cache(cache_dir, output)?;
+            } else if partial {
+                pacrs::clean_partial_downloads(cache_dir)?;
+            } else {
+                let policy = CleanPolicy {
+                    keep: keep.or(config.clean.keep).unwrap_or(0),
+                    older_than,
+                    max_size,
+                };
+                cache(
+                    uninstalled,
+                    aur,
+                    policy,
+                    show_remove_candidates,
+                    cache_dir,
+                    output,
+                )?;
+            }
+        }
+        Commands::Mark {
             packages,
             mark_group:
                 MarkGroup {
@@ -71,14 +244,45 @@ fn main() -> anyhow::Result<()> {
                     dependency,
                 },
         } => mark(&packages, explicit, dependency)?,
-        Args::Ps {
+        Commands::Ps {
             sort_by,
             shorter,
             reverse,
             quiet,
-        } => ps(sort_by, shorter, reverse, quiet)?,
+        } => ps(
+            sort_by.or(config.ps.sort_by),
+            shorter,
+            reverse,
+            quiet,
+            output,
+        )?,
     }
-    Ok(())
+    Ok(ExitCode::SUCCESS)
+}
+
+fn install(
+    packages: Vec<String>,
+    from_cache: bool,
+    dry_run: bool,
+    refresh: Refresh,
+) -> anyhow::Result<()> {
+    if dry_run {
+        return preview(&Operation::Install {
+            packages: &packages,
+            refresh,
+        });
+    }
+    pacrs::install(packages, from_cache, refresh)
+}
+
+fn remove(packages: &[String], clean_deps: bool, dry_run: bool) -> anyhow::Result<()> {
+    if dry_run {
+        return preview(&Operation::Remove {
+            packages,
+            clean_deps,
+        });
+    }
+    pacrs::remove(packages, clean_deps)
 }
 
 fn autoremove(packages: &[String]) -> anyhow::Result<()> {
@@ -89,24 +293,23 @@ fn autoremove(packages: &[String]) -> anyhow::Result<()> {
     }
 }
 
-fn update(packages: &[String], quiet: bool) {
-    match pacrs::update(packages) {
-        Ok(()) if !quiet => eprintln!(
+fn update(packages: &[String], quiet: bool) -> anyhow::Result<()> {
+    if let Err(err) = pacrs::update(packages) {
+        eprintln!(
+            "{}: The update ended with an error. \
+            You need to finish update before installing packages.",
+            "Warning".if_supports_color(Stderr, |t| t.red()),
+        );
+        return Err(err);
+    }
+    if !quiet {
+        eprintln!(
             "{}: if update system was aborted or error ends, \
             you need to finish the update before installing packages",
-            "Reminder".bright_blue()
-        ),
-        Ok(()) => {}
-        Err(error) => {
-            eprintln!("{error}");
-            eprintln!(
-                "{}: The update ended with an error. \
-                You need to finish update before installing packages.",
-                "Warning".red(),
-            );
-            std::process::exit(1);
-        }
+            "Reminder".if_supports_color(Stderr, |t| t.bright_blue())
+        );
     }
+    Ok(())
 }
 
 fn files(
@@ -132,78 +335,35 @@ fn files(
 fn cache(
     uninstalled: bool,
     aur: bool,
-    keep: u8,
+    policy: CleanPolicy,
     show_remove_candidates: bool,
+    cache_dir: Option<PathBuf>,
+    output: OutputFormat,
 ) -> anyhow::Result<()> {
     if uninstalled {
-        return pacrs::clean_cache_uninstalled();
+        return pacrs::clean_cache_uninstalled(cache_dir);
     }
     if aur {
-        return pacrs::clean_paru_cache();
+        return pacrs::clean_aur_cache(policy.keep);
     }
-    pacrs::clean_cache(keep, show_remove_candidates)?;
+    pacrs::clean_cache(policy, show_remove_candidates, output, cache_dir)?;
     if !show_remove_candidates {
         println!("You can also clean AUR cache with 'pacrs clean --aur'");
     }
     Ok(())
 }
 
-fn list_filter(list: &mut Vec<String>, packages: Vec<String>, changed: bool) {
-    if !changed && !packages.is_empty() {
-        *list = packages;
-        return;
-    }
-    list.retain(|line| packages.contains(line));
-}
-
-#[allow(clippy::fn_params_excessive_bools)]
 fn packages(
     search_regex: Option<String>,
-    orphaned: bool,
-    aur: bool,
-    explicit: bool,
-    deps: bool,
+    filter: PkgFilter,
+    output: OutputFormat,
 ) -> anyhow::Result<()> {
     search_regex.map_or_else(
-        || package_list(orphaned, aur, explicit, deps),
-        |search_regex| package_search(&search_regex),
+        || pacrs::package_list(filter, output),
+        |search_regex| package_search(&search_regex, output),
     )
 }
 
-#[allow(clippy::fn_params_excessive_bools)]
-fn package_list(orphaned: bool, aur: bool, explicit: bool, deps: bool) -> anyhow::Result<()> {
-    let mut changed = false;
-    let mut list = Vec::new();
-
-    if orphaned {
-        list_filter(&mut list, pacrs::orphaned_pkgs()?, changed);
-        changed = true;
-    }
-    if aur {
-        list_filter(&mut list, pacrs::list_aur_pkgs()?, changed);
-        changed = true;
-    }
-    if explicit {
-        list_filter(&mut list, pacrs::explicit_pkgs()?, changed);
-        changed = true;
-    }
-    if deps {
-        list_filter(&mut list, pacrs::deps()?, changed);
-        changed = true;
-    }
-
-    if !changed {
-        pacrs::installed_pkgs()?;
-        return Ok(());
-    }
-
-    for package in list {
-        println!("{package}");
-    }
-
-    Ok(())
-}
-
 fn mark(packages: &[String], explicit: bool, dependency: bool) -> anyhow::Result<()> {
     if explicit {
         return pacrs::mark_as_explicit(packages);
@@ -0,0 +1,116 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Machine-readable output of listing commands
+
+use std::io::{self, Write};
+
+use anyhow::{Context, bail};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::args::OutputFormat;
+
+/// Record printed by listing commands in the machine-readable formats
+pub trait Record: Serialize {
+    /// Names of the serialized fields in the order of TSV columns
+    const HEADERS: &'static [&'static str];
+}
+
+/// Output formats handled by [`print_records`]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum MachineFormat {
+    Json,
+    Tsv,
+}
+
+impl OutputFormat {
+    /// Machine-readable format, `None` for the human readable text which every command prints
+    /// itself
+    pub const fn machine(self) -> Option<MachineFormat> {
+        match self {
+            Self::Text => None,
+            Self::Json => Some(MachineFormat::Json),
+            Self::Tsv => Some(MachineFormat::Tsv),
+        }
+    }
+}
+
+/// Print records in the machine-readable format
+pub fn print_records<T: Record>(records: &[T], format: MachineFormat) -> anyhow::Result<()> {
+    let mut stdout = io::stdout().lock();
+    match format {
+        MachineFormat::Json => {
+            serde_json::to_writer_pretty(&mut stdout, records)
+                .context("failed to serialize output")?;
+            writeln!(stdout)?;
+        }
+        MachineFormat::Tsv => write_tsv(&mut stdout, records)?,
+    }
+    Ok(())
+}
+
+/// Write the header line followed by a line per record, the header is written even when there
+/// are no records
+fn write_tsv<T: Record>(out: &mut impl Write, records: &[T]) -> anyhow::Result<()> {
+    writeln!(out, "{}", T::HEADERS.join("\t"))?;
+    for record in records {
+        let Value::Object(fields) =
+            serde_json::to_value(record).context("failed to serialize output")?
+        else {
+            bail!("only structures can be written as TSV");
+        };
+        let row: Vec<String> = T::HEADERS
+            .iter()
+            .map(|header| fields.get(*header).map(tsv_cell).unwrap_or_default())
+            .collect();
+        writeln!(out, "{}", row.join("\t"))?;
+    }
+    Ok(())
+}
+
+fn tsv_cell(value: &Value) -> String {
+    let cell = match value {
+        Value::Null => String::new(),
+        Value::String(string) => string.clone(),
+        Value::Array(values) => values.iter().map(tsv_cell).collect::<Vec<_>>().join(","),
+        Value::Bool(_) | Value::Number(_) | Value::Object(_) => value.to_string(),
+    };
+    cell.replace(['\t', '\n'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct TestRecord {
+        name: &'static str,
+        size: u64,
+        tags: Vec<&'static str>,
+    }
+
+    impl Record for TestRecord {
+        const HEADERS: &'static [&'static str] = &["name", "size", "tags"];
+    }
+
+    fn tsv(records: &[TestRecord]) -> String {
+        let mut out = Vec::new();
+        write_tsv(&mut out, records).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn tsv_header_without_records() {
+        assert_eq!(tsv(&[]), "name\tsize\ttags\n");
+    }
+
+    #[test]
+    fn tsv_rows() {
+        let records = [TestRecord {
+            name: "a\tb",
+            size: 1,
+            tags: vec!["x", "y"],
+        }];
+        assert_eq!(tsv(&records), "name\tsize\ttags\na b\t1\tx,y\n");
+    }
+}
@@ -8,7 +8,3 @@ use crate::command::Cmd;
 pub fn files_of_installed_pkgs() -> Cmd {
     pacman().arg("-Qql")
 }
-
-pub fn installed_packages() -> Cmd {
-    pacman().arg("-Qq")
-}
@@ -1,24 +1,148 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
+use crate::alpm::{PkgFilter, architectures};
+use crate::args::OutputFormat;
+use crate::clean::{CacheEntry, CleanPolicy};
 use crate::cli::{Answer, Cli};
-use crate::cmds::{pacman, paru_if_present, paru_or_pacman, paru_or_sudo_pacman, sudo_pacman};
-use crate::temp_db::TempAlpm;
-use crate::utils::{is_root, paru_cache_dir};
-use crate::{PacrsAlpm, clean};
-use crate::{pacman, temp_db};
+use crate::cmds::{
+    aur_helper, aur_helper_if_present, aur_helper_or_pacman, aur_helper_or_sudo_pacman, pacman,
+    sudo_pacman,
+};
+use crate::output::{self, Record};
+use crate::temp_db::{Refresh, TempAlpm};
+use crate::tree::{self, TreeOptions};
+use crate::utils::is_root;
+use crate::{PacrsAlpm, aur_cache, clean, downgrade, verify, why};
 
-use anyhow::bail;
+use std::path::PathBuf;
+
+use alpm::{Db, PackageReason, Pkg};
+use anyhow::{Context, bail};
 use fs_err as fs;
 use owo_colors::OwoColorize;
+use owo_colors::Stream::{Stderr, Stdout};
+use serde::Serialize;
 
-pub fn installed_pkgs() -> anyhow::Result<()> {
-    pacman::installed_packages().execute()?;
-    Ok(())
+#[derive(Serialize)]
+struct PackageRecord<'a> {
+    name: &'a str,
+    version: &'a str,
+    reason: &'static str,
+    repo: Option<&'a str>,
+    size: i64,
 }
 
-pub fn package_search(regex: &str) -> anyhow::Result<()> {
-    pacman().args(["-Qs", regex]).execute()?;
-    Ok(())
+impl Record for PackageRecord<'_> {
+    const HEADERS: &'static [&'static str] = &["name", "version", "reason", "repo", "size"];
+}
+
+impl<'a> PackageRecord<'a> {
+    fn new(alpm: &'a PacrsAlpm, pkg: &'a Pkg) -> Self {
+        Self {
+            name: pkg.name(),
+            version: pkg.version().as_str(),
+            reason: match pkg.reason() {
+                PackageReason::Explicit => "explicit",
+                PackageReason::Depend => "dependency",
+            },
+            repo: alpm.pkg_repo(pkg.name()),
+            size: pkg.isize(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct UpdateRecord<'a> {
+    name: &'a str,
+    version: &'a str,
+    new_version: &'a str,
+    repo: Option<&'a str>,
+    /// Package which replaces the installed one
+    replaced_by: Option<&'a str>,
+    /// Package is ignored by `IgnorePkg` or `IgnoreGroup` of pacman.conf or by `downgrade`, so
+    /// update won't upgrade it
+    ignored: bool,
+}
+
+impl Record for UpdateRecord<'_> {
+    const HEADERS: &'static [&'static str] = &[
+        "name",
+        "version",
+        "new_version",
+        "repo",
+        "replaced_by",
+        "ignored",
+    ];
+}
+
+impl<'a> UpdateRecord<'a> {
+    fn new(local: &'a Pkg, sync: &'a Pkg) -> Self {
+        Self {
+            name: local.name(),
+            version: local.version().as_str(),
+            new_version: sync.version().as_str(),
+            repo: sync.db().map(Db::name),
+            replaced_by: None,
+            ignored: sync.should_ignore(),
+        }
+    }
+
+    fn replacement(local: &'a Pkg, sync: &'a Pkg) -> Self {
+        Self {
+            replaced_by: Some(sync.name()),
+            ignored: sync.should_ignore() || local.should_ignore(),
+            ..Self::new(local, sync)
+        }
+    }
+
+    /// Parse `-Qua` output line of AUR helper, e.g. "name 1.0-1 -> 1.1-1"
+    fn parse_aur(line: &'a str) -> Option<Self> {
+        let mut parts = line.split_ascii_whitespace();
+        let name = parts.next()?;
+        let version = parts.next()?;
+        let new_version = parts.nth(1)?;
+        Some(Self {
+            name,
+            version,
+            new_version,
+            repo: Some("aur"),
+            replaced_by: None,
+            ignored: false,
+        })
+    }
+}
+
+pub fn package_list(filter: PkgFilter, output: OutputFormat) -> anyhow::Result<()> {
+    let alpm = PacrsAlpm::new()?;
+    let packages = alpm.filtered_pkgs(filter);
+    let Some(format) = output.machine() else {
+        for package in packages {
+            println!("{}", package.name());
+        }
+        return Ok(());
+    };
+    let records: Vec<PackageRecord> = packages
+        .into_iter()
+        .map(|pkg| PackageRecord::new(&alpm, pkg))
+        .collect();
+    output::print_records(&records, format)
+}
+
+pub fn package_search(regex: &str, output: OutputFormat) -> anyhow::Result<()> {
+    let Some(format) = output.machine() else {
+        pacman().args(["-Qs", regex]).execute_search()?;
+        return Ok(());
+    };
+    let alpm = PacrsAlpm::new()?;
+    let packages = alpm
+        .localdb()
+        .search(std::iter::once(regex))
+        .context("failed to search in installed packages")?;
+    let records: Vec<PackageRecord> = packages
+        .iter()
+        .map(|pkg| PackageRecord::new(&alpm, pkg))
+        .collect();
+    output::print_records(&records, format)
 }
 
 pub fn info(package: &str, recursive_deps: bool) -> anyhow::Result<()> {
@@ -31,21 +155,46 @@ pub fn info(package: &str, recursive_deps: bool) -> anyhow::Result<()> {
         return Ok(());
     }
     if alpm.is_installed_pkg(package) {
-        pacman().args(["-Qi", package]).execute()?;
+        pacman().args(["-Qi", package]).capture_stderr().execute()?;
     } else {
-        paru_or_pacman().args(["-Si", package]).execute()?;
+        aur_helper_or_pacman()
+            .args(["-Si", package])
+            .capture_stderr()
+            .execute()?;
     }
     Ok(())
 }
 
+pub fn dependency_tree(package: &str, options: &TreeOptions) -> anyhow::Result<()> {
+    let alpm = PacrsAlpm::new()?;
+    tree::print_tree(&alpm, package, options)
+}
+
+pub fn why(package: &str) -> anyhow::Result<()> {
+    let alpm = PacrsAlpm::new()?;
+    why::print_why(&alpm, package)
+}
+
 pub fn search(package: &str) -> anyhow::Result<()> {
-    paru_or_pacman().args(["-Ss", package]).execute()?;
+    aur_helper_or_pacman()
+        .args(["-Ss", package])
+        .execute_search()?;
     Ok(())
 }
 
-pub fn clean_cache(keep: u8, show_remove_candidates: bool) -> anyhow::Result<()> {
+pub fn clean_cache(
+    policy: CleanPolicy,
+    show_remove_candidates: bool,
+    output: OutputFormat,
+    cache_dir: Option<PathBuf>,
+) -> anyhow::Result<()> {
     if show_remove_candidates {
-        let remove_candidates = clean::remove_candidates(keep)?;
+        let cache_dirs = clean::cache_dirs(cache_dir)?;
+        let remove_candidates = clean::remove_candidates(&cache_dirs, policy)?;
+        if let Some(format) = output.machine() {
+            let records = clean::cache_records(&remove_candidates)?;
+            return output::print_records(&records, format);
+        }
         if remove_candidates.is_empty() {
             println!("No candidates to remove");
             return Ok(());
@@ -53,24 +202,49 @@ pub fn clean_cache(keep: u8, show_remove_candidates: bool) -> anyhow::Result<()>
         clean::show_cache(&remove_candidates, false)?;
         return Ok(());
     }
-    if keep == 0 {
-        sudo_pacman().arg("-Scc").execute()?;
+    if policy.is_clean_all() {
+        sudo_pacman_clean("-Scc", cache_dir)?;
         return Ok(());
     }
-    clean::clean(keep)?;
+    let cache_dirs = clean::cache_dirs(cache_dir)?;
+    clean::clean(&cache_dirs, policy)?;
     Ok(())
 }
 
-pub fn clean_cache_uninstalled() -> anyhow::Result<()> {
-    sudo_pacman().arg("-Sc").execute()?;
+pub fn verify_cache(cache_dir: Option<PathBuf>, output: OutputFormat) -> anyhow::Result<()> {
+    let cache_dirs = clean::cache_dirs(cache_dir)?;
+    verify::verify(&cache_dirs, output)
+}
+
+pub fn clean_partial_downloads(cache_dir: Option<PathBuf>) -> anyhow::Result<()> {
+    let cache_dirs = clean::cache_dirs(cache_dir)?;
+    clean::clean_partial(&cache_dirs)
+}
+
+pub fn clean_cache_uninstalled(cache_dir: Option<PathBuf>) -> anyhow::Result<()> {
+    sudo_pacman_clean("-Sc", cache_dir)
+}
+
+fn sudo_pacman_clean(operation: &str, cache_dir: Option<PathBuf>) -> anyhow::Result<()> {
+    let mut pacman = sudo_pacman().arg(operation);
+    if let Some(cache_dir) = cache_dir {
+        pacman = pacman.arg("--cachedir").arg(cache_dir);
+    }
+    pacman.execute()?;
     Ok(())
 }
 
-pub fn install(packages: Vec<String>) -> anyhow::Result<()> {
-    let alpm = PacrsAlpm::new()?;
-    let alpm_tmp = TempAlpm::with_default_path()?;
+pub fn install(packages: Vec<String>, from_cache: bool, refresh: Refresh) -> anyhow::Result<()> {
+    if from_cache {
+        return install_from_cache(&packages);
+    }
 
+    let alpm = PacrsAlpm::new()?;
+    let alpm_tmp = TempAlpm::with_default_path(refresh)?;
     let outdated_pkgs = alpm.outdated_pkgs(&alpm_tmp);
+    // Release the lock of the temp database, so other pacrs processes don't wait for the
+    // interactive installation
+    drop(alpm_tmp);
 
     let mut recursive_pkgs = packages.clone();
     for package in &packages {
@@ -92,12 +266,23 @@ pub fn install(packages: Vec<String>) -> anyhow::Result<()> {
         );
     }
 
-    paru_or_sudo_pacman().arg("-S").args(packages).execute()?;
+    aur_helper_or_sudo_pacman()
+        .arg("-S")
+        .args(packages)
+        .execute()?;
     Ok(())
 }
 
-pub fn list_aur_pkgs() -> anyhow::Result<Vec<String>> {
-    Ok(pacman().arg("-Qmq").execute_and_grub_lines()?)
+/// Reinstall packages from the cache without checking for outdated packages
+fn install_from_cache(packages: &[String]) -> anyhow::Result<()> {
+    let archs = architectures()?;
+    let cache_dirs = clean::cache_dirs(None)?;
+    let archives = clean::newest_cached(&cache_dirs, packages, &archs)?;
+    sudo_pacman()
+        .arg("-U")
+        .args(archives.iter().map(CacheEntry::path))
+        .execute()?;
+    Ok(())
 }
 
 pub fn remove(packages: &[String], clean_deps: bool) -> anyhow::Result<()> {
@@ -110,55 +295,123 @@ pub fn remove(packages: &[String], clean_deps: bool) -> anyhow::Result<()> {
 }
 
 pub fn update(packages: &[String]) -> anyhow::Result<()> {
-    paru_or_sudo_pacman().arg("-Syu").args(packages).execute()?;
+    let mut cmd = aur_helper_or_sudo_pacman().arg("-Syu");
+    let ignored = downgrade::ignored_pkgs()?;
+    if !ignored.is_empty() {
+        cmd = cmd.arg("--ignore").arg(ignored.join(","));
+    }
+    cmd.args(packages).execute()?;
     Ok(())
 }
 
-pub fn list_updates(verbose: bool) -> anyhow::Result<()> {
-    let temp_db_path = temp_db::path()?;
-    temp_db::init(&temp_db_path, verbose)?;
-    paru_or_pacman()
-        .args(["-Qu", "--dbpath", &temp_db_path.to_string_lossy()])
-        .execute()?;
-    Ok(())
+/// Print packages which could be updated. Returns `false` if there are no updates
+pub fn list_updates(verbose: bool, refresh: Refresh, output: OutputFormat) -> anyhow::Result<bool> {
+    let alpm_tmp = TempAlpm::new(verbose, refresh)?;
+    let aur_updates = aur_updates()?;
+    let session_ignored = downgrade::ignored_pkgs()?;
+    let mut records: Vec<UpdateRecord> = alpm_tmp
+        .upgradable_pkgs()
+        .into_iter()
+        .map(|(local, sync)| UpdateRecord::new(local, sync))
+        .collect();
+    records.extend(
+        alpm_tmp
+            .replaced_pkgs()
+            .into_iter()
+            .map(|(local, sync)| UpdateRecord::replacement(local, sync)),
+    );
+    records.extend(
+        aur_updates
+            .iter()
+            .filter_map(|line| UpdateRecord::parse_aur(line)),
+    );
+    for record in &mut records {
+        // AUR helpers respect `IgnorePkg` too
+        record.ignored |= session_ignored.iter().any(|name| name == record.name)
+            || alpm_tmp.ignorepkgs().iter().any(|name| name == record.name);
+    }
+    if let Some(format) = output.machine() {
+        output::print_records(&records, format)?;
+    } else {
+        print_updates(&records);
+    }
+    Ok(records.iter().any(|record| !record.ignored))
+}
+
+fn aur_updates() -> anyhow::Result<Vec<String>> {
+    let Some(helper) = aur_helper_if_present() else {
+        return Ok(Vec::new());
+    };
+    match helper.arg("-Qua").execute_and_grub_lines() {
+        Ok(lines) => Ok(lines),
+        // AUR helper exits with non-zero code if there are no updates
+        Err(err) if err.is_nothing_found() => Ok(Vec::new()),
+        Err(err) => Err(err.into()),
+    }
 }
 
-pub fn orphaned_pkgs() -> anyhow::Result<Vec<String>> {
-    let pkgs = pacman().arg("-Qdtq").execute_and_grub_lines()?;
-    Ok(pkgs)
+fn print_updates(records: &[UpdateRecord]) {
+    let name_width = records.iter().map(|r| r.name.len()).max().unwrap_or(0);
+    let version_width = records.iter().map(|r| r.version.len()).max().unwrap_or(0);
+    let new_version_width = records
+        .iter()
+        .map(|r| r.new_version.len())
+        .max()
+        .unwrap_or(0);
+    for record in records {
+        let name = format!("{:<name_width$}", record.name);
+        let new_version = format!("{:<new_version_width$}", record.new_version);
+        let mut line = format!(
+            "{} {:<version_width$} -> {} {}",
+            name.if_supports_color(Stdout, |t| t.bold()),
+            record.version,
+            new_version.if_supports_color(Stdout, |t| t.green()),
+            record.repo.unwrap_or_default()
+        );
+        if let Some(replaced_by) = record.replaced_by {
+            line.push_str(" [replaced by ");
+            line.push_str(replaced_by);
+            line.push(']');
+        }
+        if record.ignored {
+            line.push_str(" [ignored]");
+        }
+        println!("{}", line.trim_end());
+    }
 }
 
 pub fn autoremove() -> anyhow::Result<()> {
-    if let Some(paru) = paru_if_present() {
-        paru.arg("-c").execute()?;
+    if let Some(args) = aur_helper().clean_deps_args()
+        && let Some(helper) = aur_helper_if_present()
+    {
+        helper.args(args).execute()?;
+        return Ok(());
+    }
+    let alpm = PacrsAlpm::new()?;
+    let orphaned_packages: Vec<String> = alpm
+        .orphaned_pkgs()
+        .into_iter()
+        .map(|pkg| pkg.name().to_owned())
+        .collect();
+    if orphaned_packages.is_empty() {
+        println!("No orphaned packages to remove");
         return Ok(());
     }
-    let orphaned_packages = orphaned_pkgs()?;
     remove(&orphaned_packages, true)
 }
 
-pub fn explicit_pkgs() -> anyhow::Result<Vec<String>> {
-    let pkgs = pacman().arg("-Qeq").execute_and_grub_lines()?;
-    Ok(pkgs)
-}
-
 pub fn files_of_installed_pkgs() -> anyhow::Result<()> {
     pacman().arg("-Ql").execute()?;
     Ok(())
 }
 
-pub fn deps() -> anyhow::Result<Vec<String>> {
-    let deps = pacman().arg("-Qdq").execute_and_grub_lines()?;
-    Ok(deps)
-}
-
 pub fn update_files_index(quiet: bool) -> anyhow::Result<()> {
     if is_root() {
         pacman().arg("-Fy").execute()?;
     } else if !quiet {
         eprintln!(
             "{}: Running without root privileges. Files index wouldn't be updated.",
-            "Warning".yellow()
+            "Warning".if_supports_color(Stderr, |t| t.yellow())
         );
     }
     Ok(())
@@ -168,22 +421,42 @@ pub fn mark_as_explicit(packages: &[String]) -> anyhow::Result<()> {
     pacman()
         .args(["-D", "--asexplicit"])
         .args(packages)
+        .capture_stderr()
         .execute()?;
     Ok(())
 }
 
 pub fn mark_as_dep(packages: &[String]) -> anyhow::Result<()> {
-    pacman().args(["-D", "--asdeps"]).args(packages).execute()?;
+    pacman()
+        .args(["-D", "--asdeps"])
+        .args(packages)
+        .capture_stderr()
+        .execute()?;
     Ok(())
 }
 
-pub fn clean_paru_cache() -> anyhow::Result<()> {
+pub fn clean_aur_cache(keep: u8) -> anyhow::Result<()> {
+    let helper = aur_helper();
+    let (Some(bin), Some(cache_dir), Some(clone_dir)) =
+        (helper.bin(), helper.cache_dir()?, helper.clone_dir()?)
+    else {
+        bail!("AUR helper is not used. Choose one with '--helper' flag or in the config");
+    };
+    if keep > 0 {
+        return aur_cache::clean(&clone_dir, helper.pkg_dir()?.as_deref(), keep);
+    }
+    if !cache_dir.exists() {
+        println!("AUR ({bin}) cache is empty");
+        return Ok(());
+    }
     let mut cli = Cli::new();
-    let answer = cli.confirm("You really wont to delete AUR (paru) cache?", Answer::No)?;
+    let answer = cli.confirm(
+        format!("You really wont to delete AUR ({bin}) cache?"),
+        Answer::No,
+    )?;
     if !answer.as_bool() {
         return Ok(());
     }
-    let cache_dir = paru_cache_dir()?;
     fs::remove_dir_all(cache_dir)?;
     Ok(())
 }
@@ -0,0 +1,227 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Transaction preview which doesn't touch the system
+
+use alpm::{Alpm, Dep, Pkg, PrepareData, TransFlag};
+use alpm_utils::depends;
+use anyhow::{Context, anyhow, bail};
+use bytesize::ByteSize;
+use owo_colors::{OwoColorize, Stream::Stdout};
+
+use crate::alpm::PacrsAlpm;
+use crate::temp_db::{Refresh, TempAlpm};
+
+pub enum Operation<'a> {
+    Install {
+        packages: &'a [String],
+        refresh: Refresh,
+    },
+    Remove {
+        packages: &'a [String],
+        clean_deps: bool,
+    },
+    Update {
+        packages: &'a [String],
+        refresh: Refresh,
+    },
+}
+
+#[derive(Default)]
+struct Summary {
+    install: Vec<String>,
+    upgrade: Vec<String>,
+    remove: Vec<String>,
+    download_size: i64,
+    installed_size_delta: i64,
+}
+
+/// Prepare the transaction and print what it would do. Install and update are checked against
+/// the temp sync databases, removal needs only the local database of the system
+pub fn preview(operation: &Operation) -> anyhow::Result<()> {
+    match operation {
+        Operation::Install { refresh, .. } | Operation::Update { refresh, .. } => {
+            let mut alpm = TempAlpm::with_default_path(*refresh)?;
+            run(&mut alpm, operation)
+        }
+        Operation::Remove { .. } => {
+            let mut alpm = PacrsAlpm::new()?;
+            run(&mut alpm, operation)
+        }
+    }
+}
+
+fn run(alpm: &mut Alpm, operation: &Operation) -> anyhow::Result<()> {
+    let mut flags = TransFlag::NO_LOCK;
+    if let Operation::Remove {
+        clean_deps: true, ..
+    } = operation
+    {
+        flags |= TransFlag::RECURSE;
+    }
+    alpm.trans_init(flags)
+        .context("failed to initialize transaction")?;
+    let summary = add_targets(alpm, operation).and_then(|()| prepare(alpm));
+    alpm.trans_release()
+        .context("failed to release transaction")?;
+    summary?.print();
+    Ok(())
+}
+
+fn add_targets(alpm: &Alpm, operation: &Operation) -> anyhow::Result<()> {
+    match operation {
+        Operation::Install { packages, .. } => add_sync_targets(alpm, packages),
+        Operation::Update { packages, .. } => {
+            alpm.sync_sysupgrade(false)
+                .context("failed to add system upgrade to transaction")?;
+            add_sync_targets(alpm, packages)
+        }
+        Operation::Remove { packages, .. } => {
+            for package in *packages {
+                let pkg = alpm
+                    .localdb()
+                    .pkg(package.as_str())
+                    .with_context(|| format!("{package}: package is not installed"))?;
+                alpm.trans_remove_pkg(pkg)
+                    .with_context(|| format!("{package}: failed to add package to transaction"))?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn add_sync_targets(alpm: &Alpm, packages: &[String]) -> anyhow::Result<()> {
+    for package in packages {
+        let pkg = alpm
+            .syncdbs()
+            .find_satisfier(package.as_str())
+            // AUR packages are not known by libalpm
+            .with_context(|| format!("{package}: package not found in the repos"))?;
+        alpm.trans_add_pkg(pkg)
+            .map_err(|err| anyhow!("{package}: failed to add package to transaction: {err}"))?;
+    }
+    Ok(())
+}
+
+fn prepare(alpm: &mut Alpm) -> anyhow::Result<Summary> {
+    if let Err(err) = alpm.trans_prepare() {
+        let details: Vec<String> = match err.try_data() {
+            Some(PrepareData::UnsatisfiedDeps(missing)) => missing
+                .iter()
+                .map(|missing| format!("{}: requires {}", missing.target(), missing.depend()))
+                .collect(),
+            Some(PrepareData::ConflictingDeps(conflicts)) => conflicts
+                .iter()
+                .map(|conflict| {
+                    format!(
+                        "{} and {} are in conflict",
+                        conflict.package1().name(),
+                        conflict.package2().name()
+                    )
+                })
+                .collect(),
+            Some(PrepareData::PkgInvalidArch(pkgs)) => pkgs
+                .iter()
+                .map(|pkg| format!("{}: invalid architecture", pkg.name()))
+                .collect(),
+            None => Vec::new(),
+        };
+        bail!(
+            "failed to prepare transaction: {err}\n{}",
+            details.join("\n")
+        );
+    }
+
+    let localdb = alpm.localdb();
+    let mut summary = Summary::default();
+    for pkg in alpm.trans_add() {
+        summary.download_size += pkg.download_size();
+        summary.installed_size_delta += pkg.isize();
+        if let Ok(installed) = localdb.pkg(pkg.name()) {
+            summary.installed_size_delta -= installed.isize();
+            summary.upgrade.push(format!(
+                "{} {} -> {}",
+                pkg.name(),
+                installed.version(),
+                pkg.version()
+            ));
+        } else {
+            summary
+                .install
+                .push(format!("{}-{}", pkg.name(), pkg.version()));
+        }
+    }
+    for pkg in alpm.trans_remove() {
+        summary.installed_size_delta -= pkg.isize();
+        let reason = removal_reason(alpm, pkg)
+            .map(|reason| format!(" ({reason})"))
+            .unwrap_or_default();
+        summary
+            .remove
+            .push(format!("{}-{}{reason}", pkg.name(), pkg.version()));
+    }
+    Ok(summary)
+}
+
+/// Why an installed package is removed by the sync transaction: it's replaced by a new package
+/// or conflicts with it
+fn removal_reason(alpm: &Alpm, removed: &Pkg) -> Option<String> {
+    let satisfied_by_removed = |dep: &Dep| {
+        depends::satisfies(
+            dep,
+            removed.name(),
+            removed.version(),
+            removed.provides().iter(),
+        )
+    };
+    alpm.trans_add().iter().find_map(|pkg| {
+        if pkg.replaces().iter().any(satisfied_by_removed) {
+            Some(format!("replaced by {}", pkg.name()))
+        } else if pkg.conflicts().iter().any(satisfied_by_removed) {
+            Some(format!("conflicts with {}", pkg.name()))
+        } else {
+            None
+        }
+    })
+}
+
+impl Summary {
+    fn print(&self) {
+        if self.install.is_empty() && self.upgrade.is_empty() && self.remove.is_empty() {
+            println!("Nothing to do");
+            return;
+        }
+        print_list("Packages to install", &self.install);
+        print_list("Packages to upgrade", &self.upgrade);
+        print_list("Packages to remove", &self.remove);
+        let download_size = ByteSize::b(self.download_size.unsigned_abs());
+        let size_delta = ByteSize::b(self.installed_size_delta.unsigned_abs());
+        let sign = if self.installed_size_delta < 0 {
+            "-"
+        } else {
+            "+"
+        };
+        let prompt = "==>".if_supports_color(Stdout, |t| t.green());
+        println!(
+            "{prompt} Download size: {}",
+            download_size.if_supports_color(Stdout, |t| t.bright_blue())
+        );
+        println!(
+            "{prompt} Installed size change: {}",
+            format!("{sign}{size_delta}").if_supports_color(Stdout, |t| t.bright_blue())
+        );
+    }
+}
+
+fn print_list(title: &str, pkgs: &[String]) {
+    if pkgs.is_empty() {
+        return;
+    }
+    println!(
+        "{} ({}):",
+        title.if_supports_color(Stdout, |t| t.bold()),
+        pkgs.len()
+    );
+    for pkg in pkgs {
+        println!("    {pkg}");
+    }
+}
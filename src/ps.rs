@@ -5,11 +5,13 @@ use std::collections::{BTreeSet, HashMap};
 use std::io::{BufRead, BufReader};
 
 use fs_err::File;
+use serde::Serialize;
 use sysinfo::{Pid, ProcessRefreshKind, ProcessesToUpdate, System, UpdateKind, Users};
 use tabled::settings::Style;
 use tabled::{Table, Tabled};
 
-use crate::args::PsSortBy;
+use crate::args::{OutputFormat, PsSortBy};
+use crate::output::{self, Record};
 use crate::pacman;
 use crate::utils::{JoinError, is_root};
 
@@ -18,6 +20,31 @@ struct Process {
     pid: Pid,
     user_name: String,
     command: String,
+    #[tabled(skip)]
+    deleted_files: BTreeSet<String>,
+}
+
+#[derive(Serialize)]
+struct ProcessRecord<'a> {
+    pid: u32,
+    user: &'a str,
+    command: &'a str,
+    deleted_files: &'a BTreeSet<String>,
+}
+
+impl Record for ProcessRecord<'_> {
+    const HEADERS: &'static [&'static str] = &["pid", "user", "command", "deleted_files"];
+}
+
+impl<'a> From<&'a Process> for ProcessRecord<'a> {
+    fn from(process: &'a Process) -> Self {
+        Self {
+            pid: process.pid.as_u32(),
+            user: &process.user_name,
+            command: &process.command,
+            deleted_files: &process.deleted_files,
+        }
+    }
 }
 
 impl Process {
@@ -26,6 +53,7 @@ impl Process {
             pid: process.pid(),
             user_name: process_owner(process, users),
             command: get_process_command(process).to_string(),
+            deleted_files: BTreeSet::new(),
         }
     }
 }
@@ -137,11 +165,12 @@ fn processes_with_deleted_files() -> anyhow::Result<Vec<Process>> {
 
     Ok(deleted_files_and_his_processes
         .into_iter()
-        .filter_map(|(process, files)| {
-            files
-                .iter()
-                .any(|f| pkgs_files.contains(f))
-                .then_some(process)
+        .filter_map(|(mut process, files)| {
+            process.deleted_files = files
+                .into_iter()
+                .filter(|f| pkgs_files.contains(f))
+                .collect();
+            (!process.deleted_files.is_empty()).then_some(process)
         })
         .collect())
 }
@@ -151,7 +180,14 @@ pub fn ps(
     shorter: bool,
     reverse: bool,
     quiet: bool,
+    output: OutputFormat,
 ) -> anyhow::Result<()> {
+    if let Some(format) = output.machine() {
+        let processes = sorted(processes_with_deleted_files()?, reverse, sort_by);
+        let records: Vec<ProcessRecord> = processes.iter().map(ProcessRecord::from).collect();
+        return output::print_records(&records, format);
+    }
+
     if !quiet && !is_root() {
         eprintln!(
             "Not running as root you are limited to searching for files you have permission. \
@@ -192,10 +228,16 @@ fn short_print(processes: Vec<Process>, reverse: bool) {
     }
 }
 
-fn long_print(mut processes: Vec<Process>, reverse: bool, sort_by: Option<PsSortBy>) {
+fn long_print(processes: Vec<Process>, reverse: bool, sort_by: Option<PsSortBy>) {
+    let processes = sorted(processes, reverse, sort_by);
+    let table = Table::new(&processes).with(Style::psql()).to_string();
+    println!("{table}");
+}
+
+fn sorted(mut processes: Vec<Process>, reverse: bool, sort_by: Option<PsSortBy>) -> Vec<Process> {
     match sort_by {
         None => {}
-        Some(PsSortBy::Pid) => processes.sort_by(|a, b| a.pid.cmp(&b.pid)),
+        Some(PsSortBy::Pid) => processes.sort_by_key(|p| p.pid),
         Some(PsSortBy::User) => processes.sort_by(|a, b| a.user_name.cmp(&b.user_name)),
         Some(PsSortBy::Command) => processes.sort_by(|a, b| a.command.cmp(&b.command)),
     }
@@ -204,6 +246,5 @@ fn long_print(mut processes: Vec<Process>, reverse: bool, sort_by: Option<PsSort
         processes.reverse();
     }
 
-    let table = Table::new(&processes).with(Style::psql()).to_string();
-    println!("{table}");
+    processes
 }
@@ -0,0 +1,339 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Rollback of recent transactions with archives from the package cache
+
+use std::collections::BTreeMap;
+
+use anyhow::bail;
+use owo_colors::{OwoColorize, Stream::Stderr, Stream::Stdout};
+
+use crate::alpm::{PacrsAlpm, architectures};
+use crate::clean::{self, CacheEntry};
+use crate::cli::{Answer, Cli};
+use crate::cmds::sudo_pacman;
+use crate::history::{self, Transaction};
+
+#[derive(Default)]
+struct Plan {
+    /// Archives of versions which were installed before the transactions
+    install: Vec<CacheEntry>,
+    /// Packages which were installed by the transactions
+    remove: Vec<String>,
+    /// Removed packages which are restored as dependencies. pacman log doesn't keep install
+    /// reason, so packages which are required by other installed packages are considered
+    /// dependencies
+    as_deps: Vec<String>,
+}
+
+/// Installed package as it's seen by the plan
+struct Installed {
+    version: String,
+    /// Names of dependencies
+    depends: Vec<String>,
+}
+
+/// Revert changes of the last "count" transactions from pacman log
+pub fn rollback(count: usize) -> anyhow::Result<()> {
+    let transactions = history::read()?;
+    if transactions.is_empty() {
+        println!("No transactions in pacman log");
+        return Ok(());
+    }
+    let recent = &transactions[transactions.len().saturating_sub(count)..];
+    let alpm = PacrsAlpm::new()?;
+    let cache = clean::read_cache(&clean::cache_dirs(None)?)?;
+    let plan = plan(
+        &targets(recent),
+        &installed_pkgs(&alpm),
+        &cache,
+        &architectures()?,
+    )?;
+    if plan.install.is_empty() && plan.remove.is_empty() {
+        println!("Nothing to do");
+        return Ok(());
+    }
+    print_transactions(recent);
+    plan.print();
+    let mut cli = Cli::new();
+    let answer = cli.confirm("Proceed with rollback?", Answer::Yes)?;
+    if !answer.as_bool() {
+        return Ok(());
+    }
+    // Previous versions are restored first, so nothing is removed if they can't be installed.
+    // pacman asks itself to remove packages which conflict with them
+    if !plan.install.is_empty() {
+        sudo_pacman()
+            .arg("-U")
+            .args(plan.install.iter().map(CacheEntry::path))
+            .execute()?;
+    }
+    if !plan.as_deps.is_empty() {
+        sudo_pacman()
+            .args(["-D", "--asdeps"])
+            .args(&plan.as_deps)
+            .execute()?;
+    }
+    // Restored versions don't depend on the new packages anymore. Some of them could be
+    // already removed as conflicting ones
+    let alpm = PacrsAlpm::new()?;
+    let remove: Vec<&String> = plan
+        .remove
+        .iter()
+        .filter(|package| alpm.localdb().pkg(package.as_str()).is_ok())
+        .collect();
+    if !remove.is_empty() {
+        sudo_pacman().arg("-R").args(remove).execute()?;
+    }
+    Ok(())
+}
+
+/// Versions before the earliest change of each package, `None` if it wasn't installed
+fn targets(transactions: &[Transaction]) -> BTreeMap<&str, Option<&str>> {
+    let mut targets = BTreeMap::new();
+    for trans in transactions.iter().rev() {
+        for change in trans.changes.iter().rev() {
+            targets.insert(change.package.as_str(), change.old_version.as_deref());
+        }
+    }
+    targets
+}
+
+fn installed_pkgs(alpm: &PacrsAlpm) -> BTreeMap<String, Installed> {
+    alpm.localdb()
+        .pkgs()
+        .iter()
+        .map(|pkg| {
+            let installed = Installed {
+                version: pkg.version().to_string(),
+                depends: pkg
+                    .depends()
+                    .iter()
+                    .map(|dep| dep.name().to_owned())
+                    .collect(),
+            };
+            (pkg.name().to_owned(), installed)
+        })
+        .collect()
+}
+
+fn plan(
+    targets: &BTreeMap<&str, Option<&str>>,
+    installed: &BTreeMap<String, Installed>,
+    cache: &[CacheEntry],
+    archs: &[String],
+) -> anyhow::Result<Plan> {
+    let mut plan = Plan::default();
+    let mut missing = Vec::new();
+    for (&package, &target) in targets {
+        if target.is_none() && installed.contains_key(package) {
+            plan.remove.push(package.to_owned());
+        }
+    }
+    for (&package, &target) in targets {
+        let Some(version) = target else {
+            continue;
+        };
+        let installed_pkg = installed.get(package);
+        if installed_pkg.is_some_and(|pkg| pkg.version == version) {
+            continue;
+        }
+        let archive = clean::cached_versions(cache, package, archs)
+            .into_iter()
+            .find(|entry| entry.full_version() == version);
+        let Some(archive) = archive else {
+            missing.push(format!("{package}-{version}"));
+            continue;
+        };
+        // Reason of installed packages is kept by pacman
+        if installed_pkg.is_none() && is_required(installed, package, &plan.remove) {
+            plan.as_deps.push(package.to_owned());
+        }
+        plan.install.push(archive);
+    }
+    if !missing.is_empty() {
+        bail!(
+            "archives of previous versions are not found in the cache: {}",
+            missing.join(", ")
+        );
+    }
+    Ok(plan)
+}
+
+/// One of installed packages which are not going to be removed depends on the package
+fn is_required(installed: &BTreeMap<String, Installed>, package: &str, removed: &[String]) -> bool {
+    installed
+        .iter()
+        .filter(|(name, _)| !removed.contains(name))
+        .any(|(_, pkg)| pkg.depends.iter().any(|dep| dep == package))
+}
+
+fn print_transactions(transactions: &[Transaction]) {
+    for trans in transactions {
+        trans.print_header();
+    }
+    if transactions.iter().any(|trans| !trans.completed) {
+        eprintln!(
+            "{}: not completed transactions are reverted only by their logged changes",
+            "Warning".if_supports_color(Stderr, |t| t.yellow())
+        );
+    }
+}
+
+impl Plan {
+    fn print(&self) {
+        print_list(
+            "Packages to restore",
+            self.install.iter().map(ToString::to_string),
+        );
+        print_list(
+            "Packages to mark as dependencies",
+            self.as_deps.iter().cloned(),
+        );
+        print_list("Packages to remove", self.remove.iter().cloned());
+    }
+}
+
+fn print_list(title: &str, items: impl ExactSizeIterator<Item = String>) {
+    if items.len() == 0 {
+        return;
+    }
+    println!(
+        "{} ({}):",
+        title.if_supports_color(Stdout, |t| t.bold()),
+        items.len()
+    );
+    for item in items {
+        println!("    {item}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use jiff::Timestamp;
+
+    use super::*;
+    use crate::args::HistoryAction;
+    use crate::history::Change;
+
+    fn change(package: &str, old_version: Option<&str>, new_version: Option<&str>) -> Change {
+        let action = match (old_version, new_version) {
+            (None, _) => HistoryAction::Installed,
+            (_, None) => HistoryAction::Removed,
+            _ => HistoryAction::Upgraded,
+        };
+        Change {
+            time: Timestamp::UNIX_EPOCH,
+            action,
+            package: package.to_owned(),
+            old_version: old_version.map(str::to_owned),
+            new_version: new_version.map(str::to_owned),
+        }
+    }
+
+    fn transaction(changes: Vec<Change>) -> Transaction {
+        Transaction {
+            started: Timestamp::UNIX_EPOCH,
+            command: None,
+            changes,
+            completed: true,
+        }
+    }
+
+    fn installed(packages: &[(&str, &str, &[&str])]) -> BTreeMap<String, Installed> {
+        packages
+            .iter()
+            .map(|(name, version, depends)| {
+                let pkg = Installed {
+                    version: (*version).to_owned(),
+                    depends: depends.iter().map(|dep| (*dep).to_owned()).collect(),
+                };
+                ((*name).to_owned(), pkg)
+            })
+            .collect()
+    }
+
+    fn cache(file_names: &[&str]) -> Vec<CacheEntry> {
+        let regex = clean::cache_entry_regex().unwrap();
+        let dir = Path::new("/var/cache/pacman/pkg");
+        file_names
+            .iter()
+            .map(|name| clean::parse_file_name(name, &regex, dir).unwrap())
+            .collect()
+    }
+
+    fn archs() -> Vec<String> {
+        vec!["x86_64".to_owned(), "any".to_owned()]
+    }
+
+    #[test]
+    fn earliest_old_version_wins() {
+        let transactions = [
+            transaction(vec![change("foo", Some("1.0-1"), Some("2.0-1"))]),
+            transaction(vec![
+                change("foo", Some("2.0-1"), Some("3.0-1")),
+                change("bar", None, Some("1.0-1")),
+                change("baz", Some("1.0-1"), None),
+            ]),
+            transaction(vec![change("bar", Some("1.0-1"), Some("1.1-1"))]),
+        ];
+        let targets = targets(&transactions);
+        assert_eq!(
+            targets.into_iter().collect::<Vec<_>>(),
+            [
+                ("bar", None),
+                ("baz", Some("1.0-1")),
+                ("foo", Some("1.0-1"))
+            ]
+        );
+    }
+
+    #[test]
+    fn plan_restores_and_removes() {
+        let targets = BTreeMap::from([
+            ("bar", None),
+            ("baz", Some("1.0-1")),
+            ("foo", Some("1.0-1")),
+            ("qux", Some("2.0-1")),
+        ]);
+        let installed = installed(&[
+            ("foo", "3.0-1", &[]),
+            ("bar", "1.0-1", &[]),
+            ("qux", "2.0-1", &[]),
+            ("app", "1.0-1", &["baz"]),
+        ]);
+        let cache = cache(&[
+            "foo-1.0-1-x86_64.pkg.tar.zst",
+            "foo-3.0-1-x86_64.pkg.tar.zst",
+            "baz-1.0-1-any.pkg.tar.zst",
+        ]);
+        let plan = plan(&targets, &installed, &cache, &archs()).unwrap();
+        let install: Vec<String> = plan.install.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            install,
+            ["baz-1.0-1-any.pkg.tar.zst", "foo-1.0-1-x86_64.pkg.tar.zst"]
+        );
+        assert_eq!(plan.remove, ["bar"]);
+        assert_eq!(plan.as_deps, ["baz"]);
+    }
+
+    #[test]
+    fn dependency_of_removed_package_is_restored_as_explicit() {
+        let targets = BTreeMap::from([("bar", None), ("baz", Some("1.0-1"))]);
+        let installed = installed(&[("bar", "1.0-1", &["baz"])]);
+        let cache = cache(&["baz-1.0-1-any.pkg.tar.zst"]);
+        let plan = plan(&targets, &installed, &cache, &archs()).unwrap();
+        assert_eq!(plan.remove, ["bar"]);
+        assert!(plan.as_deps.is_empty());
+    }
+
+    #[test]
+    fn plan_fails_without_cached_archive() {
+        let targets = BTreeMap::from([("foo", Some("1.0-1"))]);
+        let installed = installed(&[("foo", "2.0-1", &[])]);
+        let cache = cache(&["foo-2.0-1-x86_64.pkg.tar.zst"]);
+        let err = plan(&targets, &installed, &cache, &archs()).err().unwrap();
+        assert!(err.to_string().contains("foo-1.0-1"));
+    }
+}
@@ -0,0 +1,204 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Minimal `.SRCINFO` reader for AUR packages cloned by AUR helper
+
+use std::path::Path;
+
+use fs_err as fs;
+
+use crate::cmds::aur_helper;
+
+const SRCINFO_FILE: &str = ".SRCINFO";
+
+pub struct Srcinfo {
+    pub name: String,
+    /// Dependencies as depstrings, e.g. "foo>=1.0"
+    pub depends: Vec<String>,
+}
+
+enum Section {
+    Base,
+    Target,
+    Other,
+}
+
+/// Read `.SRCINFO` of the package from AUR helper clone directory if it exists
+pub fn find(package: &str) -> anyhow::Result<Option<Srcinfo>> {
+    let Some(clone_dir) = aur_helper().clone_dir()? else {
+        return Ok(None);
+    };
+    let Some(content) = read_srcinfo(&clone_dir, package)? else {
+        return Ok(None);
+    };
+    Ok(Some(parse(&content, package, std::env::consts::ARCH)))
+}
+
+/// Package name without version constraint
+pub fn dep_name(depstring: &str) -> &str {
+    depstring.split(['<', '>', '=']).next().unwrap_or(depstring)
+}
+
+/// Content of `.SRCINFO` which describes the package. Clones are named by pkgbase, so if the
+/// clone with the package name doesn't contain it, the package is searched in all clones
+fn read_srcinfo(clone_dir: &Path, package: &str) -> anyhow::Result<Option<String>> {
+    let same_name = clone_dir.join(package).join(SRCINFO_FILE);
+    if let Some(content) = read_if_exists(&same_name)?
+        && has_pkgname(&content, package)
+    {
+        return Ok(Some(content));
+    }
+    if !clone_dir.is_dir() {
+        return Ok(None);
+    }
+    for entry in fs::read_dir(clone_dir)? {
+        let path = entry?.path().join(SRCINFO_FILE);
+        if path == same_name {
+            continue;
+        }
+        if let Some(content) = read_if_exists(&path)?
+            && has_pkgname(&content, package)
+        {
+            return Ok(Some(content));
+        }
+    }
+    Ok(None)
+}
+
+fn read_if_exists(path: &Path) -> anyhow::Result<Option<String>> {
+    if !path.is_file() {
+        return Ok(None);
+    }
+    Ok(Some(fs::read_to_string(path)?))
+}
+
+fn has_pkgname(content: &str, package: &str) -> bool {
+    content
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .any(|(key, value)| key.trim() == "pkgname" && value.trim() == package)
+}
+
+fn parse(content: &str, package: &str, arch: &str) -> Srcinfo {
+    let arch_depends = format!("depends_{arch}");
+    let mut base = Depends::default();
+    // Package section could override dependencies of pkgbase
+    let mut overrides = Overrides::default();
+    let mut section = Section::Base;
+    for line in content.lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let (key, value) = (key.trim(), value.trim());
+        let is_arch = key == arch_depends;
+        match key {
+            "pkgbase" => section = Section::Base,
+            "pkgname" if value == package => section = Section::Target,
+            "pkgname" => section = Section::Other,
+            _ if key == "depends" || is_arch => {
+                let depends = match (&section, is_arch) {
+                    (Section::Base, false) => &mut base.common,
+                    (Section::Base, true) => &mut base.arch,
+                    (Section::Target, false) => overrides.common.get_or_insert_default(),
+                    (Section::Target, true) => overrides.arch.get_or_insert_default(),
+                    (Section::Other, _) => continue,
+                };
+                // Empty value in package section means that the package has no dependencies
+                if !value.is_empty() {
+                    depends.push(value.to_owned());
+                }
+            }
+            _ => {}
+        }
+    }
+    // Architecture specific dependencies are added to common ones and overridden separately
+    let mut depends = overrides.common.unwrap_or(base.common);
+    depends.extend(overrides.arch.unwrap_or(base.arch));
+    Srcinfo {
+        name: package.to_owned(),
+        depends,
+    }
+}
+
+#[derive(Default)]
+struct Depends {
+    common: Vec<String>,
+    arch: Vec<String>,
+}
+
+#[derive(Default)]
+struct Overrides {
+    common: Option<Vec<String>>,
+    arch: Option<Vec<String>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPLIT: &str = "\
+pkgbase = foo
+\tpkgver = 1.0
+\tpkgrel = 1
+\tarch = x86_64
+\tarch = aarch64
+\tdepends = glibc
+\tdepends = bar>=2.0
+\tdepends_x86_64 = lib-x86
+\tdepends_aarch64 = lib-arm
+
+pkgname = foo-git
+
+pkgname = foo-docs
+\tdepends =
+
+pkgname = foo-cli
+\tdepends = foo-git=1.0-1
+\tdepends_x86_64 = cli-x86
+";
+
+    #[test]
+    fn split_package_inherits_base_dependencies() {
+        let srcinfo = parse(SPLIT, "foo-git", "x86_64");
+        assert_eq!(srcinfo.name, "foo-git");
+        assert_eq!(srcinfo.depends, ["glibc", "bar>=2.0", "lib-x86"]);
+    }
+
+    #[test]
+    fn empty_depends_overrides_only_common_dependencies() {
+        let srcinfo = parse(SPLIT, "foo-docs", "x86_64");
+        assert_eq!(srcinfo.depends, ["lib-x86"]);
+    }
+
+    #[test]
+    fn package_section_overrides_dependencies() {
+        let srcinfo = parse(SPLIT, "foo-cli", "x86_64");
+        assert_eq!(srcinfo.depends, ["foo-git=1.0-1", "cli-x86"]);
+    }
+
+    #[test]
+    fn only_dependencies_of_given_arch_are_used() {
+        let srcinfo = parse(SPLIT, "foo-git", "aarch64");
+        assert_eq!(srcinfo.depends, ["glibc", "bar>=2.0", "lib-arm"]);
+        let srcinfo = parse(SPLIT, "foo-cli", "aarch64");
+        assert_eq!(srcinfo.depends, ["foo-git=1.0-1", "lib-arm"]);
+    }
+
+    #[test]
+    fn split_packages_are_found_by_pkgname() {
+        assert!(has_pkgname(SPLIT, "foo-git"));
+        assert!(has_pkgname(SPLIT, "foo-docs"));
+        assert!(!has_pkgname(SPLIT, "foo"));
+        assert!(!has_pkgname(SPLIT, "foo-"));
+    }
+
+    #[test]
+    fn version_constraint_is_removed_from_dependency() {
+        assert_eq!(dep_name("foo"), "foo");
+        assert_eq!(dep_name("foo>=1.0"), "foo");
+        assert_eq!(dep_name("foo<=1.0"), "foo");
+        assert_eq!(dep_name("foo>1.0"), "foo");
+        assert_eq!(dep_name("foo<1.0"), "foo");
+        assert_eq!(dep_name("foo=1:1.0-1"), "foo");
+        assert_eq!(dep_name("lib32-foo.so=1-64"), "lib32-foo.so");
+    }
+}
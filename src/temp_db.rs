@@ -1,61 +1,408 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions, Permissions, TryLockError};
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt};
 use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 
-use alpm::Alpm;
-use anyhow::Context;
-use derive_more::Deref;
+use alpm::{AnyDownloadEvent, DownloadEvent, DownloadResult, Package};
+use alpm_utils::DbListExt;
+use anyhow::{Context, bail};
+use derive_more::{Deref, DerefMut};
 use etcetera::BaseStrategy;
 use fs_err as fs;
+use nix::fcntl::OFlag;
+use serde::{Deserialize, Serialize};
 
-use crate::Cmd;
 use crate::alpm::{PacrsAlpm, pacmanconf};
-use crate::cmds::PACMAN_BIN;
+use crate::args::RefreshGroup;
+use crate::utils::is_root;
 
-pub fn path() -> anyhow::Result<PathBuf> {
+/// How long the temp sync databases are used without updating if TTL is not configured
+pub const DEFAULT_TTL: Duration = Duration::from_mins(15);
+/// File with time of the last update of the temp sync databases
+const FRESHNESS_FILE: &str = "freshness.json";
+/// Advisory lock which prevents concurrent pacrs processes from using the temp database
+const LOCK_FILE: &str = "pacrs.lck";
+/// Lock file which libalpm creates while it updates the databases
+const ALPM_LOCK_FILE: &str = "db.lck";
+/// Temp database which is updated by root. Users read it if it's fresh, but never write to it
+const SYSTEM_PATH: &str = "/var/lib/pacrs/sync";
+
+/// Temp database which the current user can update: system-wide one for root, otherwise the one
+/// in the user cache dir
+fn path() -> anyhow::Result<PathBuf> {
+    if is_root() {
+        return Ok(PathBuf::from(SYSTEM_PATH));
+    }
     Ok(etcetera::base_strategy::choose_base_strategy()?
         .cache_dir()
         .join("pacrs/db"))
 }
 
-#[derive(Deref)]
-pub struct TempAlpm(PacrsAlpm);
+/// When the temp sync databases are updated
+#[derive(Clone, Copy)]
+pub enum Refresh {
+    /// If they were updated earlier than the given time ago
+    Expired(Duration),
+    Always,
+    /// Only if they were never downloaded or the list of repositories was changed
+    Never,
+}
+
+/// Time of the last update of the temp sync databases
+#[derive(Serialize, Deserialize)]
+struct Freshness {
+    /// Seconds since the Unix epoch
+    synced: u64,
+    /// Modification time of each sync database file in seconds since the Unix epoch
+    dbs: BTreeMap<String, u64>,
+}
+
+#[derive(Deref, DerefMut)]
+pub struct TempAlpm {
+    #[deref]
+    #[deref_mut]
+    alpm: PacrsAlpm,
+    /// The lock is held while the handle is used and released on drop
+    _lock: File,
+}
 
 impl TempAlpm {
-    pub fn new(path: impl AsRef<Path>) -> anyhow::Result<Self> {
-        let path = path.as_ref();
+    /// Open the temp database and update its sync databases if they aren't fresh. Users reuse the
+    /// system-wide database if it's fresh instead of downloading their own copy
+    pub fn new(show_output: bool, refresh: Refresh) -> anyhow::Result<Self> {
+        if !is_root()
+            && let Some(alpm) = Self::open_shared(refresh)
+        {
+            return Ok(alpm);
+        }
+        let path = path()?;
+        if is_root() {
+            create_system_dir(&path)?;
+        } else {
+            fs::create_dir_all(&path)?;
+        }
+        let lock = lock(&path, Lock::Exclusive)?;
+        init(&path)?;
+        let mut alpm = Self::open(&path, lock)?;
+        if !alpm.is_fresh(refresh) {
+            alpm.update(show_output)?;
+            Freshness::of(&alpm).write(&path)?;
+            if is_root() {
+                make_readable(&path)?;
+            }
+        }
+        Ok(alpm)
+    }
+
+    /// Open the system-wide database for reading if it's trusted and fresh
+    fn open_shared(refresh: Refresh) -> Option<Self> {
+        let path = Path::new(SYSTEM_PATH);
+        if !is_trusted(path) {
+            return None;
+        }
+        let lock = lock(path, Lock::Shared).ok()?;
+        let alpm = Self::open(path, lock).ok()?;
+        alpm.is_fresh(refresh).then_some(alpm)
+    }
+
+    /// Open already initialized temp database without updating it
+    fn open(path: &Path, lock: File) -> anyhow::Result<Self> {
         let conf = pacmanconf()?;
-        let mut alpm = Alpm::new(&*conf.root_dir, &path.to_string_lossy())
-            .context("failed to initialize alpm connection")?;
+        let mut alpm = PacrsAlpm::with_paths(Path::new(&conf.root_dir), path)?;
         alpm_utils::configure_alpm(&mut alpm, &conf).context("failed to configure alpm")?;
-        init(path, false)?;
-        Ok(Self(PacrsAlpm::with_alpm(alpm)))
+        Ok(Self { alpm, _lock: lock })
     }
 
-    pub fn with_default_path() -> anyhow::Result<Self> {
-        let path = path()?;
-        let tmp_alpm = Self::new(path)?;
-        Ok(tmp_alpm)
+    pub fn with_default_path(refresh: Refresh) -> anyhow::Result<Self> {
+        Self::new(false, refresh)
+    }
+
+    /// Download sync databases from the mirrors in-process, so neither root nor fakeroot is
+    /// needed
+    pub fn update(&mut self, show_output: bool) -> anyhow::Result<()> {
+        if show_output {
+            self.set_dl_cb((), |file, event, ()| print_download_result(file, &event));
+        }
+        self.syncdbs_mut()
+            .update(false)
+            .context("failed to update sync databases")?;
+        Ok(())
+    }
+
+    /// Sync databases were updated by pacrs, weren't changed since and are not expired
+    fn is_fresh(&self, refresh: Refresh) -> bool {
+        let path = Path::new(self.dbpath());
+        let Some(recorded) = Freshness::read(path) else {
+            return false;
+        };
+        // Databases were changed by someone else or repositories in pacman.conf were changed
+        if Freshness::of(self).dbs != recorded.dbs {
+            return false;
+        }
+        match refresh {
+            Refresh::Expired(ttl) => recorded.age() < ttl,
+            Refresh::Always => false,
+            Refresh::Never => true,
+        }
+    }
+
+    /// Installed packages which have newer versions in the temp sync databases. Returns pairs of
+    /// installed and sync packages
+    pub fn upgradable_pkgs(&self) -> Vec<(&Package, &Package)> {
+        self.localdb()
+            .pkgs()
+            .iter()
+            .filter_map(|local| {
+                let sync = self.syncdbs().pkg(local.name()).ok()?;
+                (sync.version() > local.version()).then_some((local, sync))
+            })
+            .collect()
+    }
+
+    /// Installed packages which are replaced by packages from the temp sync databases, e.g. after
+    /// renaming. Returns pairs of installed and replacing packages
+    pub fn replaced_pkgs(&self) -> Vec<(&Package, &Package)> {
+        let localdb = self.localdb();
+        let mut replaced = Vec::new();
+        for sync in self.syncdbs().iter().flat_map(|db| db.pkgs()) {
+            if localdb.pkg(sync.name()).is_ok() {
+                continue;
+            }
+            for dep in sync.replaces() {
+                // Like pacman, only the package itself is replaced, not its providers
+                let local = localdb
+                    .pkgs()
+                    .find_satisfier(dep.to_string())
+                    .filter(|local| local.name() == dep.name());
+                if let Some(local) = local {
+                    replaced.push((local, sync));
+                }
+            }
+        }
+        replaced
+    }
+}
+
+impl Refresh {
+    /// Choose refresh mode by command line flags. `ttl` is used if no flag is given
+    pub const fn new(flags: &RefreshGroup, ttl: Duration) -> Self {
+        if flags.refresh {
+            Self::Always
+        } else if flags.no_refresh {
+            Self::Never
+        } else {
+            Self::Expired(ttl)
+        }
     }
 }
 
-pub fn init(path: impl AsRef<Path>, show_output: bool) -> anyhow::Result<()> {
-    let temp_db_path = path.as_ref();
-    fs::create_dir_all(temp_db_path)?;
+impl Freshness {
+    /// Current state of the sync databases of the handle. Time of the update is now
+    fn of(alpm: &TempAlpm) -> Self {
+        let sync_dir = Path::new(alpm.dbpath()).join("sync");
+        let mut dbs = BTreeMap::new();
+        for db in alpm.syncdbs() {
+            let modified = fs::metadata(sync_dir.join(format!("{}.db", db.name())))
+                .and_then(|metadata| metadata.modified())
+                .map_or(0, unix_secs);
+            dbs.insert(db.name().to_owned(), modified);
+        }
+        Self {
+            synced: unix_secs(SystemTime::now()),
+            dbs,
+        }
+    }
+
+    /// Read the record. Missing or damaged record is treated as absent
+    fn read(path: &Path) -> Option<Self> {
+        let content = fs::read_to_string(path.join(FRESHNESS_FILE)).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let path = path.join(FRESHNESS_FILE);
+        let mut file = open_no_follow(
+            &path,
+            OpenOptions::new().write(true).create(true).truncate(true),
+        )?;
+        file.write_all(serde_json::to_string(self)?.as_bytes())
+            .with_context(|| format!("failed to write '{}'", path.display()))?;
+        Ok(())
+    }
+
+    fn age(&self) -> Duration {
+        Duration::from_secs(unix_secs(SystemTime::now()).saturating_sub(self.synced))
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Lock {
+    /// For updating the database
+    Exclusive,
+    /// For reading the system-wide database by users
+    Shared,
+}
+
+/// Lock the temp database. If another pacrs holds the lock, wait until it's released
+fn lock(path: &Path, kind: Lock) -> anyhow::Result<File> {
+    let lock_path = path.join(LOCK_FILE);
+    let mut options = OpenOptions::new();
+    if kind == Lock::Exclusive {
+        options.write(true).create(true);
+    } else {
+        options.read(true);
+    }
+    let lock = open_no_follow(&lock_path, &mut options)?;
+    let locked = match kind {
+        Lock::Exclusive => lock.try_lock(),
+        Lock::Shared => lock.try_lock_shared(),
+    };
+    match locked {
+        Ok(()) => return Ok(lock),
+        Err(TryLockError::WouldBlock) => {}
+        Err(TryLockError::Error(err)) => {
+            return Err(err).with_context(|| format!("failed to lock '{}'", lock_path.display()));
+        }
+    }
+    eprintln!(
+        "Another pacrs process is using the temp database, waiting for it to finish (lock file \
+        '{}')",
+        lock_path.display()
+    );
+    match kind {
+        Lock::Exclusive => lock.lock(),
+        Lock::Shared => lock.lock_shared(),
+    }
+    .with_context(|| format!("failed to lock '{}'", lock_path.display()))?;
+    Ok(lock)
+}
+
+/// Open file refusing to follow a symlink in place of it
+fn open_no_follow(path: &Path, options: &mut OpenOptions) -> anyhow::Result<File> {
+    options
+        .custom_flags(OFlag::O_NOFOLLOW.bits())
+        .mode(0o644)
+        .open(path)
+        .with_context(|| format!("failed to open '{}'", path.display()))
+}
+
+/// Directory is owned by root and nobody else can write to it, so root can use it safely and
+/// users can trust its content
+fn is_trusted(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|m| m.is_dir() && m.uid() == 0 && m.mode() & 0o022 == 0)
+}
+
+/// Create the system-wide temp database directory readable by everyone. Refuse to use it if
+/// someone else could change it
+fn create_system_dir(path: &Path) -> anyhow::Result<()> {
+    if fs::symlink_metadata(path).is_err() {
+        fs::create_dir_all(path)?;
+        fs::set_permissions(path, Permissions::from_mode(0o755))?;
+    }
+    if !is_trusted(path) {
+        bail!(
+            "'{}' must be a directory owned by root and writable only by it. Remove it and \
+            pacrs will create it again",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Let users read the sync databases downloaded by root despite of umask
+fn make_readable(path: &Path) -> anyhow::Result<()> {
+    let sync_dir = path.join("sync");
+    fs::set_permissions(&sync_dir, Permissions::from_mode(0o755))?;
+    for entry in fs::read_dir(&sync_dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            fs::set_permissions(entry.path(), Permissions::from_mode(0o644))?;
+        }
+    }
+    Ok(())
+}
+
+/// Prepare the locked temp database directory: link the local database of the system and remove
+/// leftovers of interrupted updates
+fn init(temp_db_path: &Path) -> anyhow::Result<()> {
     let conf = pacmanconf()?;
-    let temp_local_db = temp_db_path.join("local");
-    if !temp_local_db.exists() {
-        let local_db = Path::new(&conf.db_path).join("local");
-        fs::os::unix::fs::symlink(local_db, temp_local_db)?;
+    let local_db = Path::new(&conf.db_path).join("local");
+    link_local_db(temp_db_path, &local_db)?;
+    // Other pacrs processes can't update the temp database while we hold the lock, so the lock
+    // file of libalpm is left by a killed process unless pacman runs with this database
+    let alpm_lock = temp_db_path.join(ALPM_LOCK_FILE);
+    if fs::symlink_metadata(&alpm_lock).is_ok() {
+        if let Some(pid) = process_using(temp_db_path) {
+            bail!(
+                "temp database is locked by process {pid}, wait until it finishes (lock file \
+                '{}')",
+                alpm_lock.display()
+            );
+        }
+        log::info!("Removing stale lock file: {}", alpm_lock.display());
+        fs::remove_file(alpm_lock)?;
     }
-    update(temp_db_path.to_string_lossy(), show_output)
+    Ok(())
+}
+
+/// Find a process which runs with the database path in its command line, e.g. `pacman --dbpath`
+fn process_using(db_path: &Path) -> Option<u32> {
+    let db_path = db_path.as_os_str().as_bytes();
+    let own_pid = std::process::id();
+    fs::read_dir("/proc")
+        .ok()?
+        .filter_map(Result::ok)
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<u32>().ok())
+        .filter(|pid| *pid != own_pid)
+        .find(|pid| {
+            std::fs::read(format!("/proc/{pid}/cmdline")).is_ok_and(|cmdline| {
+                cmdline
+                    .split(|byte| *byte == 0)
+                    .map(|arg| arg.strip_prefix(b"--dbpath=").unwrap_or(arg))
+                    .any(|arg| arg.strip_suffix(b"/").unwrap_or(arg) == db_path)
+            })
+        })
 }
 
-pub fn update(path: impl AsRef<str>, show_output: bool) -> anyhow::Result<()> {
-    let mut cmd = Cmd::new("fakeroot").args(["--", PACMAN_BIN, "-Sy", "--dbpath", path.as_ref()]);
-    if !show_output {
-        cmd = cmd.hide_output();
+/// Link the local database of the system to the temp database. Broken link or link to another
+/// place (e.g. if `DBPath` was changed in pacman.conf) is replaced
+fn link_local_db(temp_db_path: &Path, local_db: &Path) -> anyhow::Result<()> {
+    let link = temp_db_path.join("local");
+    if let Ok(metadata) = fs::symlink_metadata(&link) {
+        if metadata.is_symlink() && fs::read_link(&link)? == local_db {
+            return Ok(());
+        }
+        if metadata.is_dir() {
+            bail!(
+                "'{}' is a directory, not a link to the local database. pacrs doesn't remove \
+                it, move it away to use the temp database",
+                link.display()
+            );
+        }
+        log::info!("Replacing local database link: {}", link.display());
+        fs::remove_file(&link)?;
     }
-    cmd.execute()?;
+    fs::os::unix::fs::symlink(local_db, link)?;
     Ok(())
 }
+
+fn print_download_result(file: &str, event: &AnyDownloadEvent) {
+    let DownloadEvent::Completed(completed) = event.event() else {
+        return;
+    };
+    match completed.result {
+        DownloadResult::Success => eprintln!("{file} downloaded"),
+        DownloadResult::UpToDate => eprintln!("{file} is up to date"),
+        DownloadResult::Failed => eprintln!("failed to download {file}"),
+    }
+}
@@ -0,0 +1,153 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Dependency tree rendering
+
+use std::collections::HashSet;
+
+use alpm::Package;
+use anyhow::Context;
+
+use crate::alpm::{Dependency, PacrsAlpm};
+
+pub struct TreeOptions {
+    /// Maximum depth of the tree. Unlimited if not specified
+    pub depth: Option<usize>,
+    /// Show optional dependencies
+    pub optional: bool,
+    /// Show packages which depend on the package instead of its dependencies
+    pub reverse: bool,
+}
+
+struct Node<'a> {
+    dep: Dependency<'a>,
+    optional: bool,
+}
+
+struct TreePrinter<'a> {
+    alpm: &'a PacrsAlpm,
+    options: &'a TreeOptions,
+    /// Packages whose children are printed. Packages cut off by the depth limit are not included
+    shown: HashSet<String>,
+}
+
+pub fn print_tree(alpm: &PacrsAlpm, package: &str, options: &TreeOptions) -> anyhow::Result<()> {
+    let mut printer = TreePrinter {
+        alpm,
+        options,
+        shown: HashSet::from([package.to_owned()]),
+    };
+    let children = if options.reverse {
+        let pkg = alpm
+            .localdb()
+            .pkg(package)
+            .with_context(|| format!("{package}: package is not installed"))?;
+        printer.reverse_children(pkg)
+    } else {
+        let mut children: Vec<Node> = alpm
+            .dependencies(package)?
+            .into_iter()
+            .map(|dep| Node {
+                dep,
+                optional: false,
+            })
+            .collect();
+        if options.optional
+            && let Some(pkg) = alpm.find_pkg(package)
+        {
+            children.extend(printer.optional_children(pkg));
+        }
+        children
+    };
+    println!("{package}");
+    printer.print_children(children, "", 1);
+    Ok(())
+}
+
+impl<'a> TreePrinter<'a> {
+    fn print_children(&mut self, children: Vec<Node<'a>>, prefix: &str, depth: usize) {
+        let count = children.len();
+        for (i, node) in children.into_iter().enumerate() {
+            let (branch, indent) = if i + 1 == count {
+                ("└── ", "    ")
+            } else {
+                ("├── ", "│   ")
+            };
+            let name = node.dep.name();
+            let optional = if node.optional { " (optional)" } else { "" };
+            if self.shown.contains(name) {
+                println!("{prefix}{branch}{name}{optional} (already shown)");
+                continue;
+            }
+            println!("{prefix}{branch}{name}{optional}");
+            if self.options.depth.is_some_and(|max| depth >= max) {
+                continue;
+            }
+            // Marked before the children are printed, so dependency cycles end here
+            self.shown.insert(name.to_owned());
+            let children = self.children(&node.dep);
+            self.print_children(children, &format!("{prefix}{indent}"), depth + 1);
+        }
+    }
+
+    fn children(&self, dep: &Dependency<'a>) -> Vec<Node<'a>> {
+        if self.options.reverse {
+            return dep
+                .as_pkg()
+                .map(|pkg| self.reverse_children(pkg))
+                .unwrap_or_default();
+        }
+        let mut children: Vec<Node> = self
+            .alpm
+            .dep_deps(dep)
+            // Unresolvable dependencies are just not shown
+            .unwrap_or_default()
+            .into_iter()
+            .map(|dep| Node {
+                dep,
+                optional: false,
+            })
+            .collect();
+        if self.options.optional
+            && let Some(pkg) = dep.as_pkg()
+        {
+            children.extend(self.optional_children(pkg));
+        }
+        children
+    }
+
+    fn optional_children(&self, pkg: &'a Package) -> Vec<Node<'a>> {
+        self.alpm
+            .optional_deps(pkg)
+            .into_iter()
+            .map(|dep| Node {
+                dep,
+                optional: true,
+            })
+            .collect()
+    }
+
+    fn reverse_children(&self, pkg: &'a Package) -> Vec<Node<'a>> {
+        let localdb = self.alpm.localdb();
+        let mut children: Vec<Node> = pkg
+            .required_by()
+            .iter()
+            .filter_map(|name| localdb.pkg(name).ok())
+            .map(|pkg| Node {
+                dep: Dependency::Pkg(pkg),
+                optional: false,
+            })
+            .collect();
+        if self.options.optional {
+            children.extend(
+                pkg.optional_for()
+                    .iter()
+                    .filter_map(|name| localdb.pkg(name).ok())
+                    .map(|pkg| Node {
+                        dep: Dependency::Pkg(pkg),
+                        optional: true,
+                    }),
+            );
+        }
+        children
+    }
+}
@@ -1,10 +1,8 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
-use std::path::PathBuf;
 use std::thread::JoinHandle;
 
-use anyhow::{Context, anyhow};
-use etcetera::BaseStrategy;
+use anyhow::anyhow;
 use nix::unistd::getuid;
 
 use crate::command::Cmd;
@@ -17,11 +15,13 @@ pub fn is_root() -> bool {
     getuid().is_root()
 }
 
-pub fn paru_cache_dir() -> anyhow::Result<PathBuf> {
-    Ok(etcetera::choose_base_strategy()
-        .context("failed to find paru cache dir")?
-        .cache_dir()
-        .join("paru"))
+/// Empty directory in the system temp dir which is unique for the test
+#[cfg(test)]
+pub fn test_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("pacrs-test-{}-{name}", std::process::id()));
+    let _ = fs_err::remove_dir_all(&dir);
+    fs_err::create_dir_all(&dir).unwrap();
+    dir
 }
 
 pub trait JoinError<T> {
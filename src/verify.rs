@@ -0,0 +1,191 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Integrity verification of package archives in the cache
+
+use std::fmt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use alpm::SigLevel;
+use owo_colors::{OwoColorize, Stream::Stdout};
+use serde::Serialize;
+
+use crate::alpm::PacrsAlpm;
+use crate::args::OutputFormat;
+use crate::clean::{self, CacheEntry};
+use crate::cli::{Answer, Cli};
+use crate::output::{self, Record};
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+enum Problem {
+    /// Archive has zero size
+    Empty,
+    /// Archive can't be read till the end, e.g. download was interrupted
+    Corrupted,
+    MissingSignature,
+    InvalidSignature,
+    /// Archive couldn't be checked, e.g. the keyring is missing or the file can't be read. It
+    /// isn't known to be damaged, so it's never removed
+    Unverified,
+}
+
+#[derive(Serialize)]
+struct Issue {
+    path: PathBuf,
+    problem: Problem,
+    /// Error which prevented the verification
+    error: Option<String>,
+    #[serde(skip)]
+    entry: CacheEntry,
+}
+
+impl Record for Issue {
+    const HEADERS: &'static [&'static str] = &["path", "problem", "error"];
+}
+
+/// Check every archive in the cache and offer to remove damaged ones
+pub fn verify(cache_dirs: &[PathBuf], output: OutputFormat) -> anyhow::Result<()> {
+    let alpm = PacrsAlpm::new()?;
+    let archives = clean::read_cache(cache_dirs)?;
+    let mut issues = Vec::new();
+    for entry in &archives {
+        issues.extend(check(&alpm, entry));
+    }
+    if let Some(format) = output.machine() {
+        return output::print_records(&issues, format);
+    }
+    for issue in &issues {
+        if let Some(error) = &issue.error {
+            println!(
+                "{}: {}: {error}",
+                issue.path.display(),
+                issue.problem.if_supports_color(Stdout, |t| t.yellow())
+            );
+        } else {
+            println!(
+                "{}: {}",
+                issue.path.display(),
+                issue.problem.if_supports_color(Stdout, |t| t.red())
+            );
+        }
+    }
+    let (unverified, damaged): (Vec<Issue>, Vec<Issue>) = issues
+        .into_iter()
+        .partition(|issue| issue.problem == Problem::Unverified);
+    let unverified_note = if unverified.is_empty() {
+        String::new()
+    } else {
+        format!(", {} could not be verified", unverified.len())
+    };
+    let summary = format!(
+        "{} archives checked, {} damaged{unverified_note}",
+        archives.len(),
+        damaged.len()
+    );
+    let prompt = "==>".if_supports_color(Stdout, |t| t.green());
+    println!(
+        "{prompt} {}",
+        summary.if_supports_color(Stdout, |t| t.bold())
+    );
+    if damaged.is_empty() {
+        return Ok(());
+    }
+    remove_damaged(&damaged)
+}
+
+fn check(alpm: &PacrsAlpm, entry: &CacheEntry) -> Option<Issue> {
+    let path = entry.path();
+    log::info!("Verifying archive: {}", path.display());
+    match path.metadata() {
+        Ok(metadata) if metadata.size() == 0 => {
+            return Some(Issue::new(entry, Problem::Empty, None));
+        }
+        Ok(_) => {}
+        Err(err) => {
+            return Some(Issue::new(
+                entry,
+                Problem::Unverified,
+                Some(err.to_string()),
+            ));
+        }
+    }
+    let has_sig = entry.sig_path().exists();
+    // Without signature only integrity of the archive itself could be checked
+    let sig_level = if has_sig {
+        SigLevel::PACKAGE
+    } else {
+        SigLevel::empty()
+    };
+    match alpm.pkg_load(path_bytes(&path), true, sig_level) {
+        Ok(_) if has_sig => None,
+        Ok(_) | Err(alpm::Error::SigMissing | alpm::Error::PkgMissingSig) => {
+            Some(Issue::new(entry, Problem::MissingSignature, None))
+        }
+        Err(alpm::Error::PkgInvalidSig | alpm::Error::SigInvalid) => {
+            Some(Issue::new(entry, Problem::InvalidSignature, None))
+        }
+        // Checksum mismatch, broken metadata or archive which can't be read till the end
+        Err(
+            alpm::Error::PkgInvalid
+            | alpm::Error::PkgInvalidChecksum
+            | alpm::Error::PkgInvalidName
+            | alpm::Error::Libarchive,
+        ) => Some(Issue::new(entry, Problem::Corrupted, None)),
+        Err(err) => Some(Issue::new(
+            entry,
+            Problem::Unverified,
+            Some(err.to_string()),
+        )),
+    }
+}
+
+impl Issue {
+    fn new(entry: &CacheEntry, problem: Problem, error: Option<String>) -> Self {
+        Self {
+            path: entry.path(),
+            problem,
+            error,
+            entry: entry.clone(),
+        }
+    }
+}
+
+fn path_bytes(path: &Path) -> Vec<u8> {
+    path.as_os_str().as_bytes().to_vec()
+}
+
+fn remove_damaged(damaged: &[Issue]) -> anyhow::Result<()> {
+    let mut cli = Cli::new();
+    let answer = cli.confirm("Remove damaged archives?", Answer::No)?;
+    if !answer.as_bool() {
+        return Ok(());
+    }
+    let mut total_size = 0;
+    let mut files_count = 0;
+    for damaged in damaged {
+        total_size += clean::remove_file(&damaged.path)?;
+        files_count += 1;
+        let sig_path = damaged.entry.sig_path();
+        if sig_path.exists() {
+            total_size += clean::remove_file(&sig_path)?;
+            files_count += 1;
+        }
+    }
+    clean::print_removed_stats(damaged.len(), files_count, total_size);
+    Ok(())
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            Self::Empty => "empty file",
+            Self::Corrupted => "corrupted or truncated archive",
+            Self::MissingSignature => "signature is missing",
+            Self::InvalidSignature => "invalid signature",
+            Self::Unverified => "could not verify",
+        };
+        f.write_str(description)
+    }
+}
@@ -0,0 +1,131 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Explanation of why the package is installed
+
+use std::collections::{HashMap, VecDeque};
+
+use alpm::{Dep, Package, PackageReason};
+use anyhow::Context;
+use owo_colors::OwoColorize;
+use owo_colors::Stream::{Stderr, Stdout};
+
+use crate::alpm::PacrsAlpm;
+
+pub fn print_why(alpm: &PacrsAlpm, package: &str) -> anyhow::Result<()> {
+    let pkg = alpm
+        .localdb()
+        .pkg(package)
+        .with_context(|| format!("{package}: package is not installed"))?;
+    let explicit = pkg.reason() == PackageReason::Explicit;
+    let reason = if explicit {
+        "explicitly"
+    } else {
+        "as a dependency"
+    };
+    println!(
+        "{} is installed {reason}",
+        package.if_supports_color(Stdout, |t| t.bold())
+    );
+
+    let paths = shortest_paths(alpm, pkg);
+    if paths.is_empty() {
+        println!("No explicitly installed package depends on it");
+    } else {
+        println!("Required by explicitly installed packages:");
+        for path in &paths {
+            println!("  {}", format_path(path));
+        }
+    }
+
+    match (explicit, paths.is_empty()) {
+        (true, true) => eprintln!(
+            "{}: marking it as dependency will make it orphaned",
+            "Warning".if_supports_color(Stderr, |t| t.yellow())
+        ),
+        (true, false) => println!("It can be safely marked as dependency"),
+        (false, true) => println!("It is orphaned and can be removed with 'pacrs autoremove'"),
+        (false, false) => {}
+    }
+    Ok(())
+}
+
+/// Every shortest path from an explicitly installed package to the given one.
+/// Each path starts with the explicit package and ends with the given one
+fn shortest_paths<'a>(alpm: &'a PacrsAlpm, target: &'a Package) -> Vec<Vec<&'a Package>> {
+    let localdb = alpm.localdb();
+    // Packages which are one step closer to the target on the shortest paths
+    let mut next: HashMap<&str, Vec<&Package>> = HashMap::new();
+    let mut distance: HashMap<&str, usize> = HashMap::from([(target.name(), 0)]);
+    let mut roots = Vec::new();
+    let mut queue = VecDeque::from([target]);
+    while let Some(pkg) = queue.pop_front() {
+        let pkg_distance = distance[pkg.name()];
+        // Package is the reason of installation itself so we don't look further
+        if pkg_distance > 0 && pkg.reason() == PackageReason::Explicit {
+            roots.push(pkg);
+            continue;
+        }
+        // `required_by` also takes into account packages which depend on provided names
+        for name in pkg.required_by().iter() {
+            let Ok(dependent) = localdb.pkg(name) else {
+                continue;
+            };
+            let dependent_distance = *distance.entry(dependent.name()).or_insert_with(|| {
+                queue.push_back(dependent);
+                pkg_distance + 1
+            });
+            if dependent_distance == pkg_distance + 1 {
+                next.entry(dependent.name()).or_default().push(pkg);
+            }
+        }
+    }
+
+    let mut paths = Vec::new();
+    for root in roots {
+        collect_paths(&next, vec![root], &mut paths);
+    }
+    paths
+}
+
+fn collect_paths<'a>(
+    next: &HashMap<&str, Vec<&'a Package>>,
+    path: Vec<&'a Package>,
+    paths: &mut Vec<Vec<&'a Package>>,
+) {
+    let last = path[path.len() - 1];
+    let Some(steps) = next.get(last.name()) else {
+        paths.push(path);
+        return;
+    };
+    for step in steps {
+        let mut path = path.clone();
+        path.push(step);
+        collect_paths(next, path, paths);
+    }
+}
+
+fn format_path(path: &[&Package]) -> String {
+    let mut steps = vec![path[0].name().to_owned()];
+    for pair in path.windows(2) {
+        let (dependent, pkg) = (pair[0], pair[1]);
+        let step = provided_dep(dependent, pkg).map_or_else(
+            || pkg.name().to_owned(),
+            |provided| format!("{} (provides {provided})", pkg.name()),
+        );
+        steps.push(step);
+    }
+    steps.join(" -> ")
+}
+
+/// Name of the dependency which the package satisfies through `provides`
+/// if the dependent doesn't depend on the package directly
+fn provided_dep<'a>(dependent: &'a Package, pkg: &Package) -> Option<&'a str> {
+    let depends = dependent.depends();
+    if depends.iter().any(|dep| dep.name() == pkg.name()) {
+        return None;
+    }
+    depends
+        .iter()
+        .map(Dep::name)
+        .find(|name| pkg.provides().iter().any(|provide| provide.name() == *name))
+}